@@ -0,0 +1,440 @@
+//! Compiling Brainfuck into Boolfuck.
+//!
+//! Each Brainfuck cell takes nine Boolfuck cells: a cell that is zero
+//! whenever the pointer is on it, followed by the eight bits of the byte,
+//! least significant bit first. `.` and `,` write and read those bits in
+//! the same order as [`utils::bits_from_u8`], so a translated program
+//! produces the same bytes under [`boolfuck`](crate::boolfuck) as the
+//! original does under a Brainfuck interpreter with wrapping 8-bit cells.
+//!
+//! ```
+//! use boolfuck::{boolfuck, brainfuck};
+//!
+//! let code = brainfuck::translate("++++++++[>++++++++<-]>+.").unwrap();
+//! assert_eq!(boolfuck(&code, vec![]).unwrap(), b"A");
+//! ```
+//!
+//! [`from_boolfuck`] goes the other way, for running Boolfuck on a
+//! Brainfuck engine.
+
+use super::*;
+
+/// How each Brainfuck command is spelled in Boolfuck.
+const TRANSLATIONS: [(char, &str); 8] = [
+    ('+', ">[>]+<[+<]>>>>>>>>>[+]<<<<<<<<<"),
+    ('-', ">>>>>>>>>+<<<<<<<<+[>+]<[<]>>>>>>>>>[+]<<<<<<<<<"),
+    ('<', "<<<<<<<<<"),
+    ('>', ">>>>>>>>>"),
+    (',', ">,>,>,>,>,>,>,>,<<<<<<<<"),
+    ('.', ">;>;>;>;>;>;>;>;<<<<<<<<"),
+    ('[', ">>>>>>>>>+<<<<<<<<+[>+]<[<]>>>>>>>>>[+<<<<<<<<[>]+<[+<]"),
+    (']', ">>>>>>>>>+<<<<<<<<+[>+]<[<]>>>>>>>>>]<[+<]")
+];
+
+/// Compiles Brainfuck `source` into Boolfuck instructions, ignoring every
+/// character that is not a Brainfuck command.
+///
+/// Positions in the error refer to `source`.
+pub fn compile(source: &str) -> Result<Vec<Instruction>, ParseError> {
+    let commands: Vec<_> = parser::positions(source)
+        .filter(|(ch, _)| TRANSLATIONS.iter().any(|(command, _)| command == ch))
+        .collect();
+
+    parser::check_brackets(commands.iter().filter_map(|&(ch, position)| match ch {
+        '[' => Some((Instruction::SkipRight, position)),
+        ']' => Some((Instruction::SkipLeft, position)),
+        _ => None
+    }))?;
+
+    Ok(commands.iter()
+        .flat_map(|&(ch, _)| translation(ch).chars().filter_map(parser::parse_instruction))
+        .collect())
+}
+
+/// Compiles Brainfuck `source` into Boolfuck source.
+pub fn translate(source: &str) -> Result<String, ParseError> {
+    Ok(compile(source)?.iter().map(|instruction| instruction.to_char()).collect())
+}
+
+/// Compiles Boolfuck `instructions` into a Brainfuck program that writes
+/// the same bytes for the same input, with trailing bits padded as
+/// [`PartialByte::Pad`] does. Unbalanced brackets carry over as they are.
+///
+/// The program needs 8-bit wrapping cells, a tape unbounded to the right
+/// and a `,` that stores zero or leaves the cell alone at end of input.
+///
+/// Each Boolfuck cell takes a frame of [`FRAME`] Brainfuck cells, and the
+/// input and output state moves along with the pointer from frame to
+/// frame. Frame 0 is kept empty: moving left onto it shifts every frame in
+/// use one to the right, so the Boolfuck tape can grow left without the
+/// Brainfuck one doing so.
+pub fn from_boolfuck(instructions: &[Instruction]) -> String {
+    let mut emitter = Emitter { code: String::new(), at: 0 };
+
+    emitter.go(FRAME as isize);
+    emitter.at = MARK;
+    emitter.add(MARK, 1);
+    emitter.add(WEIGHT, 1);
+    emitter.go(BIT);
+
+    for instruction in instructions {
+        match instruction {
+            Instruction::Flip => emitter.flip(),
+            Instruction::Read => emitter.read(),
+            Instruction::Write => emitter.write(),
+            Instruction::MoveLeft => emitter.move_left(),
+            Instruction::MoveRight => emitter.move_right(),
+            Instruction::SkipRight => emitter.open(BIT),
+            Instruction::SkipLeft => emitter.close(BIT)
+        }
+        emitter.go(BIT);
+    }
+
+    emitter.flush();
+    emitter.code
+}
+
+/// The number of Brainfuck cells standing for one Boolfuck cell.
+pub const FRAME: usize = 10;
+
+// The cells of a frame, as offsets from its start. `MARK` is one in every
+// frame visited so far and `BIT` holds the Boolfuck cell. The input byte
+// being split into bits, how many of its bits are left, the output byte
+// being built and the weight of its next bit are only kept in the frame
+// under the pointer. The rest are scratch cells, zero between
+// instructions.
+const MARK: isize = 0;
+const BIT: isize = 1;
+const INPUT: isize = 2;
+const INPUT_LEFT: isize = 3;
+const OUTPUT: isize = 4;
+const WEIGHT: isize = 5;
+const TEMP: isize = 6;
+const FLAG: isize = 7;
+const HALF: isize = 8;
+const QUOTIENT: isize = 9;
+
+const STATE: [isize; 4] = [INPUT, INPUT_LEFT, OUTPUT, WEIGHT];
+
+/// Writes Brainfuck while tracking which cell of the current frame the
+/// pointer is on, so code can be written in terms of cells.
+struct Emitter {
+    code: String,
+    at: isize
+}
+
+impl Emitter {
+    fn go(&mut self, cell: isize) {
+        let (step, count) = if cell > self.at { ('>', cell - self.at) } else { ('<', self.at - cell) };
+        self.code.extend((0 .. count).map(|_| step));
+        self.at = cell;
+    }
+
+    fn add(&mut self, cell: isize, n: usize) {
+        self.go(cell);
+        self.code.extend((0 .. n).map(|_| '+'));
+    }
+
+    fn sub(&mut self, cell: isize) {
+        self.go(cell);
+        self.code.push('-');
+    }
+
+    fn open(&mut self, cell: isize) {
+        self.go(cell);
+        self.code.push('[');
+    }
+
+    fn close(&mut self, cell: isize) {
+        self.go(cell);
+        self.code.push(']');
+    }
+
+    fn clear(&mut self, cell: isize) {
+        self.open(cell);
+        self.sub(cell);
+        self.close(cell);
+    }
+
+    /// Adds `from` to each of `to`, `times` times over, leaving `from` zero.
+    fn transfer(&mut self, from: isize, to: &[isize], times: usize) {
+        self.open(from);
+        self.sub(from);
+        for &cell in to {
+            self.add(cell, times);
+        }
+        self.close(from);
+    }
+
+    /// Sets `FLAG` to one if `cell` is zero and to zero otherwise.
+    fn is_zero(&mut self, cell: isize) {
+        self.add(FLAG, 1);
+        self.open(cell);
+        self.sub(cell);
+        self.add(TEMP, 1);
+        self.clear(FLAG);
+        self.close(cell);
+        self.transfer(TEMP, &[cell], 1);
+    }
+
+    /// Moves the state into the frame `by` frames along.
+    fn carry_state(&mut self, by: isize) {
+        for &cell in &STATE {
+            self.transfer(cell, &[cell + by * FRAME as isize], 1);
+        }
+    }
+
+    fn flip(&mut self) {
+        self.add(TEMP, 1);
+        self.open(BIT);
+        self.sub(BIT);
+        self.sub(TEMP);
+        self.close(BIT);
+        self.transfer(TEMP, &[BIT], 1);
+    }
+
+    fn read(&mut self) {
+        // Fetch a byte once the last one is used up.
+        self.is_zero(INPUT_LEFT);
+        self.open(FLAG);
+        self.sub(FLAG);
+        self.clear(INPUT);
+        self.code.push(',');
+        self.add(INPUT_LEFT, 8);
+        self.close(FLAG);
+
+        // Halve the byte, keeping its low bit in `HALF`.
+        self.open(INPUT);
+        self.sub(INPUT);
+        self.add(TEMP, 1);
+        self.open(HALF);
+        self.sub(HALF);
+        self.sub(TEMP);
+        self.add(QUOTIENT, 1);
+        self.close(HALF);
+        self.transfer(TEMP, &[HALF], 1);
+        self.close(INPUT);
+
+        self.transfer(QUOTIENT, &[INPUT], 1);
+        self.sub(INPUT_LEFT);
+        self.clear(BIT);
+        self.transfer(HALF, &[BIT], 1);
+    }
+
+    fn write(&mut self) {
+        self.transfer(BIT, &[TEMP, FLAG], 1);
+        self.transfer(FLAG, &[BIT], 1);
+        self.open(TEMP);
+        self.sub(TEMP);
+        self.transfer(WEIGHT, &[OUTPUT, FLAG], 1);
+        self.transfer(FLAG, &[WEIGHT], 1);
+        self.close(TEMP);
+
+        // The weight wraps to zero once all eight bits are in.
+        self.transfer(WEIGHT, &[FLAG], 2);
+        self.transfer(FLAG, &[WEIGHT], 1);
+        self.is_zero(WEIGHT);
+        self.open(FLAG);
+        self.sub(FLAG);
+        self.go(OUTPUT);
+        self.code.push('.');
+        self.clear(OUTPUT);
+        self.add(WEIGHT, 1);
+        self.close(FLAG);
+    }
+
+    fn move_right(&mut self) {
+        self.carry_state(1);
+        self.go(FRAME as isize);
+        self.at = 0;
+        self.clear(MARK);
+        self.add(MARK, 1);
+    }
+
+    fn move_left(&mut self) {
+        self.carry_state(-1);
+        self.go(-(FRAME as isize));
+        self.at = 0;
+
+        // Only frame 0 is unmarked on the left.
+        self.is_zero(MARK);
+        self.open(FLAG);
+        self.sub(FLAG);
+        self.go(MARK);
+        let right = ">".repeat(FRAME);
+        let left = "<".repeat(FRAME);
+        // Find the last frame in use, then move each frame one to the
+        // right, working back towards frame 0.
+        self.code.push_str(&format!("{}[{}]{}", right, right, left));
+        self.code.push_str(&format!("[-{}+{}>[-{}+{}]<{}]", right, left, right, left, left));
+        self.carry_state(1);
+        self.add(MARK + FRAME as isize, 1);
+        self.go(FLAG + FRAME as isize);
+        self.at = FLAG;
+        self.close(FLAG);
+    }
+
+    /// Writes out a partly built output byte, padded with zero bits.
+    fn flush(&mut self) {
+        self.sub(WEIGHT);
+        self.open(WEIGHT);
+        self.sub(WEIGHT);
+        self.add(TEMP, 1);
+        self.clear(FLAG);
+        self.add(FLAG, 1);
+        self.close(WEIGHT);
+        self.transfer(TEMP, &[WEIGHT], 1);
+        self.add(WEIGHT, 1);
+        self.open(FLAG);
+        self.sub(FLAG);
+        self.go(OUTPUT);
+        self.code.push('.');
+        self.clear(OUTPUT);
+        self.close(FLAG);
+    }
+}
+
+/// The Boolfuck for the Brainfuck `command`.
+pub(crate) fn translation(command: char) -> &'static str {
+    TRANSLATIONS.iter()
+        .find(|(ch, _)| *ch == command)
+        .map(|(_, code)| *code)
+        .expect("not a Brainfuck command")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testing::*;
+
+    /// Runs Brainfuck with 8-bit wrapping cells, a tape that only grows to
+    /// the right and a `,` that leaves the cell alone at end of input.
+    /// Returns `None` if it does not halt within `steps`.
+    fn run_brainfuck(code: &str, input: &[u8], steps: u64) -> Option<Vec<u8>> {
+        let code: Vec<char> = code.chars().collect();
+        let mut matches = vec![0; code.len()];
+        let mut open = vec![];
+        for (i, &ch) in code.iter().enumerate() {
+            match ch {
+                '[' => open.push(i),
+                ']' => {
+                    let j = open.pop().unwrap();
+                    matches[i] = j;
+                    matches[j] = i;
+                },
+                _ => {}
+            }
+        }
+
+        let mut tape = vec![0u8];
+        let mut pointer = 0;
+        let mut input = input.iter();
+        let mut output = vec![];
+        let mut i = 0;
+
+        for _ in 0 .. steps {
+            if i == code.len() {
+                return Some(output);
+            }
+
+            match code[i] {
+                '+' => tape[pointer] = tape[pointer].wrapping_add(1),
+                '-' => tape[pointer] = tape[pointer].wrapping_sub(1),
+                '>' => {
+                    pointer += 1;
+                    if pointer == tape.len() {
+                        tape.push(0);
+                    }
+                },
+                '<' => pointer = pointer.checked_sub(1).expect("ran off the left of the tape"),
+                ',' => {
+                    if let Some(&byte) = input.next() {
+                        tape[pointer] = byte;
+                    }
+                },
+                '.' => output.push(tape[pointer]),
+                '[' if tape[pointer] == 0 => i = matches[i],
+                ']' if tape[pointer] != 0 => i = matches[i],
+                _ => {}
+            }
+            i += 1;
+        }
+
+        None
+    }
+
+    fn assert_same_output(code: &str, input: &[u8]) {
+        let instructions = parser::parse(code).unwrap();
+        let expected = boolfuck(code, input.to_vec()).unwrap();
+        assert_eq!(run_brainfuck(&from_boolfuck(&instructions), input, 10_000_000), Some(expected), "program {}", code);
+    }
+
+    const HELLO: &str = "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.";
+
+    #[test]
+    fn test_compile() {
+        assert_eq!(compile("+").unwrap(), parser::parse(translation('+')).unwrap());
+        assert_eq!(compile("a > b").unwrap(), parser::parse(">>>>>>>>>").unwrap());
+        assert_eq!(compile("").unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_translate() {
+        assert_eq!(translate("<.").unwrap(), format!("{}{}", translation('<'), translation('.')));
+        assert_eq!(Program::parse(&translate(HELLO).unwrap()).unwrap().instructions(), &compile(HELLO).unwrap()[..]);
+    }
+
+    #[test]
+    fn test_hello_world() {
+        assert_eq!(boolfuck(&translate(HELLO).unwrap(), vec![]).unwrap(), b"Hello World!\n");
+    }
+
+    #[test]
+    fn test_io() {
+        let cat = translate(",[.,]").unwrap();
+        assert_eq!(boolfuck(&cat, b"meow".to_vec()).unwrap(), b"meow");
+
+        let upper = translate(",[--------------------------------.,]").unwrap();
+        assert_eq!(boolfuck(&upper, b"abc".to_vec()).unwrap(), b"ABC");
+    }
+
+    #[test]
+    fn test_wrap_around() {
+        assert_eq!(boolfuck(&translate("-.").unwrap(), vec![]).unwrap(), [255]);
+        assert_eq!(boolfuck(&translate("-+.").unwrap(), vec![]).unwrap(), [0]);
+        assert_eq!(boolfuck(&translate("--[-->+<]>.").unwrap(), vec![]).unwrap(), [127]);
+    }
+
+    #[test]
+    fn test_from_boolfuck() {
+        assert_same_output(testing::HELLO, b"");
+        assert_same_output(">,>,>,>,>,>,>,>,<<<<<<<<>;>;>;>;>;>;>;>;", b"hi");
+        assert_same_output(",;,;,;,;,;,;,;,;,;,;,;,;,;,;,;,;", b"ok");
+        assert_same_output("+;;;", b"");
+        assert_same_output("", b"");
+    }
+
+    #[test]
+    fn test_from_boolfuck_grows_left() {
+        assert_same_output("+<<+<+[;<]>>>>;>;>;;;;;", b"");
+        assert_same_output(">+<<<+[>;]<<<<+;<<[+<]>;", b"");
+    }
+
+    #[test]
+    fn test_from_boolfuck_matches_reference() {
+        for seed in 0 .. 150 {
+            let instructions = random_program(seed, 30);
+            let reference = Program::unoptimized(instructions.clone()).unwrap();
+            if let Some(expected) = run(reference, &testing::INPUT, 2_000) {
+                let output = run_brainfuck(&from_boolfuck(&instructions), &testing::INPUT, 10_000_000);
+                assert_eq!(output, Some(expected.output), "program {:?}", instructions);
+            }
+        }
+    }
+
+    #[test]
+    fn test_unmatched() {
+        assert_eq!(compile("+\n+]"), Err(ParseError::UnmatchedClose(Position { offset: 3, line: 2, column: 2 })));
+        assert_eq!(compile("[[]"), Err(ParseError::UnmatchedOpen(Position { offset: 0, line: 1, column: 1 })));
+    }
+}
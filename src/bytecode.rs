@@ -0,0 +1,275 @@
+//! A compact binary form of programs, to save the parsing of large
+//! generated programs.
+//!
+//! Bytecode is the magic bytes `BFBC`, a version byte, the number of
+//! instructions as a little-endian `u32`, the instructions packed 3 bits
+//! each, least significant bits first, and a little-endian CRC-32 of
+//! everything before it.
+//!
+//! ```
+//! use boolfuck::{bytecode, Program};
+//!
+//! let program = Program::parse("+[>;]").unwrap();
+//! let bytes = bytecode::encode(&program).unwrap();
+//! assert_eq!(bytecode::decode(&bytes).unwrap(), program);
+//! assert_eq!(bytecode::disassemble(&bytes).unwrap(), "+[>;]");
+//! ```
+
+use std::convert::TryFrom;
+use std::fs;
+use std::path::Path;
+use super::*;
+
+pub const MAGIC: &[u8; 4] = b"BFBC";
+pub const VERSION: u8 = 1;
+
+const HEADER: usize = 9;
+const CHECKSUM: usize = 4;
+
+/// Bytes that are not the bytecode of a program.
+#[derive (Debug)]
+pub enum BytecodeError {
+    /// Reading or writing a file failed.
+    Io(io::Error),
+    /// The bytes do not start with [`MAGIC`].
+    NotBytecode,
+    /// The bytecode is from a version this crate cannot read.
+    UnsupportedVersion(u8),
+    /// There are fewer or more bytes than the header says.
+    WrongLength,
+    /// The checksum does not match the contents.
+    Checksum { expected: u32, found: u32 },
+    /// The instruction at this index has no meaning.
+    InvalidInstruction(usize),
+    /// The brackets do not balance.
+    Unbalanced(ParseError),
+    /// The program has more instructions than the header can count.
+    TooLong(usize)
+}
+
+impl fmt::Display for BytecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "I/O error: {}", e),
+            Self::NotBytecode => write!(f, "not Boolfuck bytecode"),
+            Self::UnsupportedVersion(version) => write!(f, "unsupported bytecode version {}", version),
+            Self::WrongLength => write!(f, "bytecode has the wrong length"),
+            Self::Checksum { expected, found } => write!(f, "bytecode checksum is {:08x}, expected {:08x}", found, expected),
+            Self::InvalidInstruction(index) => write!(f, "invalid instruction {}", index),
+            Self::Unbalanced(e) => e.fmt(f),
+            Self::TooLong(len) => write!(f, "{} instructions are too many for bytecode", len)
+        }
+    }
+}
+
+impl std::error::Error for BytecodeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            Self::Unbalanced(e) => Some(e),
+            _ => None
+        }
+    }
+}
+
+impl From<io::Error> for BytecodeError {
+    fn from(e: io::Error) -> BytecodeError {
+        BytecodeError::Io(e)
+    }
+}
+
+const CODES: [Instruction; 7] = [
+    Instruction::Flip,
+    Instruction::Read,
+    Instruction::Write,
+    Instruction::MoveLeft,
+    Instruction::MoveRight,
+    Instruction::SkipRight,
+    Instruction::SkipLeft
+];
+
+/// The IEEE CRC-32 of `bytes`, as zlib and PNG compute it.
+fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0, |crc, &byte| {
+        (0 .. 8).fold(crc ^ byte as u32, |crc, _| if crc & 1 == 1 { crc >> 1 ^ 0xedb8_8320 } else { crc >> 1 })
+    })
+}
+
+/// Whether `bytes` look like bytecode rather than source.
+pub fn is_bytecode(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+/// The instruction count stored in the header for `len` instructions.
+fn count(len: usize) -> Result<u32, BytecodeError> {
+    u32::try_from(len).map_err(|_| BytecodeError::TooLong(len))
+}
+
+/// The bytecode of `program`.
+pub fn encode(program: &Program) -> Result<Vec<u8>, BytecodeError> {
+    let instructions = program.instructions();
+    let mut bytes = MAGIC.to_vec();
+    bytes.push(VERSION);
+    bytes.extend(count(instructions.len())?.to_le_bytes());
+
+    let start = bytes.len();
+    bytes.resize(start + (3 * instructions.len()).div_ceil(8), 0);
+    for (i, instruction) in instructions.iter().enumerate() {
+        let code = (CODES.iter().position(|c| c == instruction).unwrap() as u16) << (3 * i % 8);
+        let at = start + 3 * i / 8;
+        bytes[at] |= code as u8;
+        if code > 0xff {
+            bytes[at + 1] |= (code >> 8) as u8;
+        }
+    }
+
+    bytes.extend(crc32(&bytes).to_le_bytes());
+    Ok(bytes)
+}
+
+/// The instructions in `bytes`, which need not balance.
+fn instructions(bytes: &[u8]) -> Result<Vec<Instruction>, BytecodeError> {
+    if !is_bytecode(bytes) {
+        return Err(BytecodeError::NotBytecode);
+    }
+    match bytes.get(4) {
+        Some(&VERSION) => {},
+        Some(&version) => return Err(BytecodeError::UnsupportedVersion(version)),
+        None => return Err(BytecodeError::WrongLength)
+    }
+    if bytes.len() < HEADER + CHECKSUM {
+        return Err(BytecodeError::WrongLength);
+    }
+
+    let count = u32::from_le_bytes([bytes[5], bytes[6], bytes[7], bytes[8]]) as usize;
+    // The count is untrusted, and three bits per instruction can overflow
+    // a 32-bit usize.
+    let packed_len = count.checked_mul(3).ok_or(BytecodeError::WrongLength)?.div_ceil(8);
+    if bytes.len() != HEADER + packed_len + CHECKSUM {
+        return Err(BytecodeError::WrongLength);
+    }
+
+    let (contents, checksum) = bytes.split_at(bytes.len() - CHECKSUM);
+    let found = u32::from_le_bytes([checksum[0], checksum[1], checksum[2], checksum[3]]);
+    let expected = crc32(contents);
+    if found != expected {
+        return Err(BytecodeError::Checksum { expected, found });
+    }
+
+    let packed = &contents[HEADER ..];
+    (0 .. count).map(|i| {
+        let at = 3 * i / 8;
+        let pair = packed[at] as u16 | (*packed.get(at + 1).unwrap_or(&0) as u16) << 8;
+        let code = (pair >> (3 * i % 8) & 7) as usize;
+        CODES.get(code).copied().ok_or(BytecodeError::InvalidInstruction(i))
+    }).collect()
+}
+
+/// The program in `bytes`.
+pub fn decode(bytes: &[u8]) -> Result<Program, BytecodeError> {
+    Program::new(instructions(bytes)?).map_err(BytecodeError::Unbalanced)
+}
+
+/// The Boolfuck source of the instructions in `bytes`, one character per
+/// instruction. Unbalanced brackets are shown as they are.
+pub fn disassemble(bytes: &[u8]) -> Result<String, BytecodeError> {
+    Ok(instructions(bytes)?.iter().map(|instruction| instruction.to_char()).collect())
+}
+
+/// Writes the bytecode of `program` to the file at `path`.
+pub fn save(program: &Program, path: impl AsRef<Path>) -> Result<(), BytecodeError> {
+    Ok(fs::write(path, encode(program)?)?)
+}
+
+/// Reads the program from the bytecode file at `path`.
+pub fn load(path: impl AsRef<Path>) -> Result<Program, BytecodeError> {
+    decode(&fs::read(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, process};
+    use testing::*;
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for seed in 0 .. 50 {
+            let program = Program::new(random_program(seed, seed as usize)).unwrap();
+            let bytes = encode(&program).unwrap();
+            assert_eq!(bytes.len(), HEADER + (3 * program.len()).div_ceil(8) + CHECKSUM);
+            assert_eq!(decode(&bytes).unwrap(), program);
+        }
+    }
+
+    #[test]
+    fn test_encode() {
+        let bytes = encode(&Program::parse("+,;<>[]").unwrap()).unwrap();
+        assert_eq!(&bytes[.. HEADER], b"BFBC\x01\x07\0\0\0");
+        // Codes 0 to 6, three bits each from the least significant bit.
+        assert_eq!(&bytes[HEADER .. bytes.len() - CHECKSUM], [0x88, 0xc6, 0x1a]);
+    }
+
+    #[test]
+    fn test_disassemble() {
+        let bytes = encode(&Program::parse("a + [ b ; ] c").unwrap()).unwrap();
+        assert_eq!(disassemble(&bytes).unwrap(), "+[;]");
+
+        let mut unbalanced = encode(&Program::parse("[]").unwrap()).unwrap();
+        unbalanced[HEADER] = 0b110_110;
+        let length = unbalanced.len() - CHECKSUM;
+        unbalanced.truncate(length);
+        unbalanced.extend(crc32(&unbalanced).to_le_bytes());
+        assert_eq!(disassemble(&unbalanced).unwrap(), "]]");
+        assert!(matches!(decode(&unbalanced), Err(BytecodeError::Unbalanced(ParseError::UnmatchedClose(_)))));
+    }
+
+    #[test]
+    fn test_errors() {
+        let bytes = encode(&Program::parse("+[>;]").unwrap()).unwrap();
+
+        assert!(matches!(decode(b"+[>;]"), Err(BytecodeError::NotBytecode)));
+        assert!(matches!(decode(&bytes[.. bytes.len() - 1]), Err(BytecodeError::WrongLength)));
+        assert!(matches!(decode(&bytes[.. 6]), Err(BytecodeError::WrongLength)));
+
+        let mut huge = bytes.clone();
+        huge[5 .. 9].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(decode(&huge), Err(BytecodeError::WrongLength)));
+
+        let mut newer = bytes.clone();
+        newer[4] = 2;
+        assert!(matches!(decode(&newer), Err(BytecodeError::UnsupportedVersion(2))));
+        assert!(matches!(decode(b"BFBC\x02"), Err(BytecodeError::UnsupportedVersion(2))));
+
+        let mut corrupt = bytes.clone();
+        corrupt[HEADER] ^= 1;
+        assert!(matches!(decode(&corrupt), Err(BytecodeError::Checksum { .. })));
+
+        let mut invalid = bytes;
+        invalid[HEADER] |= 7;
+        let length = invalid.len() - CHECKSUM;
+        invalid.truncate(length);
+        invalid.extend(crc32(&invalid).to_le_bytes());
+        assert!(matches!(decode(&invalid), Err(BytecodeError::InvalidInstruction(0))));
+
+        let len = u32::MAX as usize + 1;
+        assert!(matches!(count(len), Err(BytecodeError::TooLong(n)) if n == len));
+        assert_eq!(count(u32::MAX as usize).unwrap(), u32::MAX);
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let path = env::temp_dir().join(format!("boolfuck-bytecode-{}.bfc", process::id()));
+        let program = Program::parse(&brainfuck::translate(",[.,]").unwrap()).unwrap();
+        save(&program, &path).unwrap();
+        assert_eq!(load(&path).unwrap(), program);
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(load(&path), Err(BytecodeError::Io(_))));
+    }
+}
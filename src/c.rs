@@ -0,0 +1,224 @@
+//! Translating programs into C.
+//!
+//! The generated file is self-contained: it keeps the tape packed 64
+//! cells to a word, growing it in whichever direction the pointer goes,
+//! reads input from stdin and writes output to stdout a byte at a time,
+//! least significant bit first as [`utils::to_bytes`] does. It behaves like
+//! an [`Interpreter`] with the default [`Options`].
+//!
+//! ```
+//! let program = boolfuck::Program::parse("+[;>]").unwrap();
+//! let code = boolfuck::c::generate(&program);
+//! assert!(code.contains("int main(void)"));
+//! ```
+
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use super::*;
+
+const PRELUDE: &str = r#"#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+static uint64_t *tape;
+static long words = 1;
+/* The index of cell 0 among the bits of the tape. */
+static long origin = 0;
+
+static void ensure(long p) {
+    while (p + origin < 0) {
+        uint64_t *grown = calloc(2 * words, sizeof *grown);
+        if (!grown) abort();
+        memcpy(grown + words, tape, words * sizeof *tape);
+        free(tape);
+        tape = grown;
+        origin += words * 64;
+        words *= 2;
+    }
+    while (p + origin >= words * 64) {
+        tape = realloc(tape, 2 * words * sizeof *tape);
+        if (!tape) abort();
+        memset(tape + words, 0, words * sizeof *tape);
+        words *= 2;
+    }
+}
+
+static int get(long p) {
+    long bit = p + origin;
+    return tape[bit >> 6] >> (bit & 63) & 1;
+}
+
+static void set(long p, int value) {
+    long bit = p + origin;
+    uint64_t mask = (uint64_t)1 << (bit & 63);
+    if (value) tape[bit >> 6] |= mask; else tape[bit >> 6] &= ~mask;
+}
+
+static void flip(long p) {
+    long bit = p + origin;
+    tape[bit >> 6] ^= (uint64_t)1 << (bit & 63);
+}
+
+static int get_byte(long p) {
+    int byte = 0;
+    for (int i = 0; i < 8; i++) byte |= get(p + i) << i;
+    return byte;
+}
+
+static void set_byte(long p, int byte) {
+    for (int i = 0; i < 8; i++) set(p + i, byte >> i & 1);
+}
+
+/* Visits the cells a byte op touches and checks that the cells around
+   the byte are zero. */
+static int byte_clear(long p) {
+    ensure(p + 9);
+    return !get(p) && !get(p + 9);
+}
+
+static int in_byte, in_bits;
+static int out_byte, out_bits;
+
+static int read_bit(void) {
+    if (in_bits == 0) {
+        int c = getchar();
+        if (c == EOF) return 0;
+        in_byte = c;
+        in_bits = 8;
+    }
+    int bit = in_byte & 1;
+    in_byte >>= 1;
+    in_bits--;
+    return bit;
+}
+
+static void write_bit(int bit) {
+    out_byte |= bit << out_bits++;
+    if (out_bits == 8) {
+        putchar(out_byte);
+        out_byte = out_bits = 0;
+    }
+}
+
+int main(void) {
+    long p = 0;
+    tape = calloc(words, sizeof *tape);
+    if (!tape) abort();
+"#;
+
+const EPILOGUE: &str = r#"    if (out_bits) putchar(out_byte);
+    return 0;
+}
+"#;
+
+/// Translates `program` into a C program. Its ops are translated one by
+/// one, jumping between them with `goto`.
+pub fn generate(program: &Program) -> String {
+    let ops = program.ops();
+    let labels = jump_targets(ops);
+    let mut code = PRELUDE.to_string();
+
+    for (i, op) in ops.iter().enumerate() {
+        if labels.contains(&i) {
+            writeln!(code, "op{}:", i).unwrap();
+        }
+
+        let line = match *op {
+            Op::Flip => "flip(p);".to_string(),
+            Op::Read => "set(p, read_bit());".to_string(),
+            Op::Write => "write_bit(get(p));".to_string(),
+            Op::Move { by, .. } => format!("p += {}; ensure(p);", by),
+            Op::SkipRight(target) => format!("if (!get(p)) goto op{};", target),
+            Op::SkipLeft(target) => format!("if (get(p)) goto op{};", target),
+            Op::SetZero => "set(p, 0);".to_string(),
+            Op::ScanRight => "while (get(p)) { p++; ensure(p); }".to_string(),
+            Op::ScanLeft => "while (get(p)) { p--; ensure(p); }".to_string(),
+            Op::IncByte(len) => format!("if (byte_clear(p)) {{ set_byte(p + 1, (get_byte(p + 1) + 1) & 255); goto op{}; }}", i + 1 + len),
+            Op::DecByte(len) => format!("if (byte_clear(p)) {{ set_byte(p + 1, (get_byte(p + 1) + 255) & 255); goto op{}; }}", i + 1 + len),
+            Op::ReadByte(_) | Op::WriteByte(_) => continue,
+            Op::ByteLoopStart(len, target) => format!(
+                "if (byte_clear(p)) {{ if (!get_byte(p + 1)) goto op{}; goto op{}; }}",
+                ir::past_translation(ops, target), i + 1 + len
+            ),
+            Op::ByteLoopEnd(len, target) => format!(
+                "if (byte_clear(p)) {{ if (get_byte(p + 1)) goto op{}; goto op{}; }}",
+                ir::past_translation(ops, target), i + 1 + len
+            )
+        };
+
+        writeln!(code, "    {}", line).unwrap();
+    }
+
+    if labels.contains(&ops.len()) {
+        writeln!(code, "op{}:", ops.len()).unwrap();
+    }
+    code.push_str(EPILOGUE);
+    code
+}
+
+/// The ops that are jumped to.
+fn jump_targets(ops: &[Op]) -> BTreeSet<usize> {
+    let mut labels = BTreeSet::new();
+
+    for (i, op) in ops.iter().enumerate() {
+        match *op {
+            Op::SkipRight(target) | Op::SkipLeft(target) => {
+                labels.insert(target);
+            },
+            Op::IncByte(len) | Op::DecByte(len) => {
+                labels.insert(i + 1 + len);
+            },
+            Op::ByteLoopStart(len, target) | Op::ByteLoopEnd(len, target) => {
+                labels.insert(i + 1 + len);
+                labels.insert(ir::past_translation(ops, target));
+            },
+            _ => {}
+        }
+    }
+
+    labels
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, process};
+    use testing::*;
+
+    /// Compiles the C for each program with the system compiler and runs
+    /// it on its input, or returns `None` if there is no compiler.
+    fn run_c(cases: &[(Program, Vec<u8>)]) -> Option<Vec<Vec<u8>>> {
+        let dir = env::temp_dir().join(format!("boolfuck-c-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let outputs = cases
+            .iter()
+            .enumerate()
+            .map(|(i, (program, input))| {
+                let source = dir.join(format!("program{}.c", i));
+                let binary = dir.join(format!("program{}", i));
+                fs::write(&source, generate(program)).unwrap();
+
+                let compiled = process::Command::new("cc").arg("-O1").arg("-o").arg(&binary).arg(&source).status().ok()?;
+                assert!(compiled.success(), "cc failed on {}", source.display());
+                run_piped(&mut process::Command::new(&binary), input)
+            })
+            .collect();
+        fs::remove_dir_all(&dir).unwrap();
+        outputs
+    }
+
+    #[test]
+    fn test_generate() {
+        let code = generate(&Program::parse("+[>]<[;+]").unwrap());
+        assert!(code.contains("flip(p);"));
+        assert!(code.contains("while (get(p)) { p++; ensure(p); }"));
+        assert!(code.ends_with(EPILOGUE));
+    }
+
+    #[test]
+    fn test_matches_interpreter() {
+        assert_generator_matches(run_c);
+    }
+}
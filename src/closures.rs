@@ -0,0 +1,342 @@
+//! Running programs as a tree of closures.
+//!
+//! Instead of dispatching on each op as the [`Interpreter`] does, a
+//! [`Compiled`] program binds it to closures once, ahead of the run. The
+//! flips, moves, clears and I/O between jumps form a basic block, which
+//! one closure runs knowing where each of its cells lies from where the
+//! block starts, so its moves cost nothing. Each loop is a closure that
+//! runs its body while the cell under the pointer is one, and translated
+//! Brainfuck keeps the byte fast paths of the interpreter.
+//!
+//! ```
+//! use boolfuck::{closures::Compiled, Options, Program};
+//!
+//! let compiled = Compiled::new(&Program::parse(",;,;,;,;,;,;,;,;").unwrap());
+//! let output = compiled.run(std::io::Cursor::new(vec![0x5a]), Options::default()).unwrap();
+//! assert_eq!(output, [0x5a]);
+//! ```
+
+use std::io::{Read, Write};
+use std::mem;
+use super::*;
+use input::Input;
+use output::Output;
+
+/// The state of one run.
+struct Machine {
+    tape: Tape,
+    pointer: isize,
+    input: Input,
+    output: Output,
+    options: Options
+}
+
+impl Machine {
+    fn bit(&self) -> Bit {
+        self.tape.get(self.pointer)
+    }
+
+    fn move_to(&mut self, position: isize) -> Result<(), RuntimeError> {
+        self.tape.visit_within(position ..= position, self.options.tape_limit)?;
+        self.pointer = position;
+        Ok(())
+    }
+
+    fn run(&mut self, cell: Cell, position: isize) -> Result<(), RuntimeError> {
+        match cell {
+            Cell::Flip => self.tape.flip(position),
+            Cell::SetZero => self.tape.set(position, Bit::Zero),
+            Cell::Write => self.output.write_bit(self.tape.get(position), self.options.bit_order)?,
+            Cell::Read => {
+                let bit = match self.input.read_bit(self.options.bit_order)? {
+                    Some(bit) => bit,
+                    None => self.options.eof.bit(self.tape.get(position))?
+                };
+                self.tape.set(position, bit);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn loop_byte(&mut self) -> Option<u8> {
+        self.tape.loop_byte(self.pointer, self.options.tape_limit)
+    }
+}
+
+type Block = Box<dyn Fn(&mut Machine) -> Result<(), RuntimeError> + Send + Sync>;
+
+/// A program bound to closures, ready to run any number of times.
+pub struct Compiled {
+    block: Block
+}
+
+impl Compiled {
+    pub fn new(program: &Program) -> Compiled {
+        Compiled { block: block(program.ops(), 0, program.ops().len()) }
+    }
+
+    /// Runs the program with `options`, reading input from `reader`, and
+    /// returns the bytes it wrote.
+    pub fn run(&self, reader: impl Read + Send + 'static, options: Options) -> Result<Vec<u8>, RuntimeError> {
+        let machine = self.run_with(Input::from_reader(reader), Output::buffer(), options)?;
+        Ok(machine.output.bytes().to_vec())
+    }
+
+    /// Runs the program like [`Compiled::run`], writing each byte of
+    /// output to `writer` as soon as it is complete.
+    pub fn run_to(&self, reader: impl Read + Send + 'static, writer: impl Write + Send + 'static, options: Options) -> Result<(), RuntimeError> {
+        self.run_with(Input::from_reader(reader), Output::to_writer(writer), options)?;
+        Ok(())
+    }
+
+    fn run_with(&self, input: Input, output: Output, options: Options) -> Result<Machine, RuntimeError> {
+        let mut machine = Machine { tape: Tape::new(), pointer: 0, input, output, options };
+        (self.block)(&mut machine)?;
+        machine.output.finish(options.partial_byte, options.bit_order)?;
+        Ok(machine)
+    }
+}
+
+/// What a basic block does to one of its cells.
+#[derive (Copy, Clone, Debug)]
+enum Cell {
+    Flip,
+    SetZero,
+    Read,
+    Write
+}
+
+/// A basic block under construction. Offsets are from the pointer where
+/// the block starts.
+#[derive (Default)]
+struct Straight {
+    /// Each cell op with the offset of its cell and the lowest and
+    /// highest offsets the moves before it reach.
+    cells: Vec<(Cell, isize, isize, isize)>,
+    by: isize,
+    low: isize,
+    high: isize
+}
+
+impl Straight {
+    fn push(&mut self, cell: Cell) {
+        self.cells.push((cell, self.by, self.low, self.high));
+    }
+
+    fn move_by(&mut self, by: isize, low: isize, high: isize) {
+        self.low = self.low.min(self.by + low);
+        self.high = self.high.max(self.by + high);
+        self.by += by;
+    }
+
+    /// The closure running the block, if it does anything. The limit is
+    /// checked once for the whole block, and only if that fails is it
+    /// checked before each cell op, so the output stops where the moves
+    /// one at a time would have stopped it.
+    fn compile(self) -> Option<Block> {
+        let Straight { cells, by, low, high } = self;
+        if cells.is_empty() && low == 0 && high == 0 {
+            return None;
+        }
+
+        Some(Box::new(move |m| {
+            let from = m.pointer;
+            let limit = m.options.tape_limit;
+            // Only the tape limit looks at the visited cells.
+            if limit.is_some() && m.tape.visit_within(from + low ..= from + high, limit).is_err() {
+                for &(cell, offset, low, high) in &cells {
+                    m.tape.visit_within(from + low ..= from + high, limit)?;
+                    m.run(cell, from + offset)?;
+                }
+                return m.tape.visit_within(from + low ..= from + high, limit);
+            }
+
+            for &(cell, offset, ..) in &cells {
+                m.run(cell, from + offset)?;
+            }
+            m.pointer = from + by;
+            Ok(())
+        }))
+    }
+}
+
+/// The closure running the ops from `start` up to `end`, which hold whole
+/// loops.
+fn block(ops: &[Op], start: usize, end: usize) -> Block {
+    let mut steps: Vec<Block> = vec![];
+    let mut straight = Straight::default();
+    let mut i = start;
+
+    while i < end {
+        match ops[i] {
+            Op::Flip => straight.push(Cell::Flip),
+            Op::SetZero => straight.push(Cell::SetZero),
+            Op::Read => straight.push(Cell::Read),
+            Op::Write => straight.push(Cell::Write),
+            Op::Move { by, low, high } => straight.move_by(by, low, high),
+            _ => {
+                steps.extend(mem::take(&mut straight).compile());
+                let (step, next) = jump(ops, i);
+                steps.push(step);
+                i = next;
+                continue;
+            }
+        }
+
+        i += 1;
+    }
+
+    steps.extend(straight.compile());
+    match steps.len() {
+        0 => Box::new(|_| Ok(())),
+        1 => steps.pop().unwrap(),
+        _ => Box::new(move |m| {
+            for step in &steps {
+                step(m)?;
+            }
+            Ok(())
+        })
+    }
+}
+
+/// The closure running the op at `i`, which ends a basic block, and the
+/// index of the op after everything it runs.
+fn jump(ops: &[Op], i: usize) -> (Block, usize) {
+    match ops[i] {
+        Op::SkipRight(target) => {
+            let body = block(ops, i + 1, target);
+            (Box::new(move |m| {
+                while m.bit() == Bit::One {
+                    body(m)?;
+                }
+                Ok(())
+            }), target + 1)
+        },
+        Op::ScanRight => (Box::new(|m| m.move_to(m.tape.scan_right(m.pointer))), i + 1),
+        Op::ScanLeft => (Box::new(|m| m.move_to(m.tape.scan_left(m.pointer))), i + 1),
+        Op::IncByte(len) | Op::DecByte(len) => {
+            let delta = if let Op::IncByte(_) = ops[i] { 1 } else { u8::MAX };
+            let translation = block(ops, i + 1, i + 1 + len);
+            (Box::new(move |m| {
+                if m.tape.add_to_byte(m.pointer, delta, m.options.tape_limit) {
+                    return Ok(());
+                }
+                translation(m)
+            }), i + 1 + len)
+        },
+        Op::ReadByte(len) => {
+            let translation = block(ops, i + 1, i + 1 + len);
+            (Box::new(move |m| {
+                if m.tape.read_byte(m.pointer, &mut m.input, m.options.bit_order, m.options.tape_limit)? {
+                    return Ok(());
+                }
+                translation(m)
+            }), i + 1 + len)
+        },
+        Op::WriteByte(len) => {
+            let translation = block(ops, i + 1, i + 1 + len);
+            (Box::new(move |m| {
+                if m.tape.write_byte(m.pointer, &mut m.output, m.options.bit_order, m.options.tape_limit)? {
+                    return Ok(());
+                }
+                translation(m)
+            }), i + 1 + len)
+        },
+        Op::ByteLoopStart(len, target) => byte_loop(ops, i, len, target),
+        Op::Flip | Op::SetZero | Op::Read | Op::Write | Op::Move { .. } => unreachable!("{:?} belongs in a basic block", ops[i]),
+        Op::SkipLeft(_) | Op::ByteLoopEnd(..) => unreachable!("loops are compiled whole")
+    }
+}
+
+/// The closure running the translated Brainfuck loop whose
+/// `ByteLoopStart` is at `i` and whose `ByteLoopEnd` is at `end`.
+///
+/// The translations of its brackets are each split at the one bracket
+/// they hold: the start into the ops before its `[` and after it, and the
+/// end into the ops before its `]` and after it. Whenever a fast path
+/// does not apply, those parts run as the bracket they surround would.
+fn byte_loop(ops: &[Op], i: usize, len: usize, end: usize) -> (Block, usize) {
+    let end_len = match ops[end] {
+        Op::ByteLoopEnd(len, _) => len,
+        op => unreachable!("{:?} is not a byte loop end", op)
+    };
+    let body_start = i + 1 + len;
+    let past = end + 1 + end_len;
+    let open = (i + 1 .. body_start).find(|&j| matches!(ops[j], Op::SkipRight(target) if target >= body_start)).expect("the translation of [ holds a [");
+    let close = (end + 1 .. past).find(|&j| matches!(ops[j], Op::SkipLeft(target) if target <= end)).expect("the translation of ] holds a ]");
+
+    let before_open = block(ops, i + 1, open);
+    let after_open = block(ops, open + 1, body_start);
+    let body = block(ops, body_start, end);
+    let before_close = block(ops, end + 1, close);
+    let after_close = block(ops, close + 1, past);
+
+    (Box::new(move |m| {
+        if let Some(byte) = m.loop_byte() {
+            if byte == 0 {
+                return Ok(());
+            }
+        } else {
+            before_open(m)?;
+            if m.bit() == Bit::Zero {
+                return after_close(m);
+            }
+            after_open(m)?;
+        }
+
+        loop {
+            body(m)?;
+
+            if let Some(byte) = m.loop_byte() {
+                if byte == 0 {
+                    return Ok(());
+                }
+                continue;
+            }
+
+            before_close(m)?;
+            if m.bit() == Bit::Zero {
+                return after_close(m);
+            }
+            after_open(m)?;
+        }
+    }), past)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testing::*;
+
+    fn compiled(program: &Program, input: &[u8], options: Options) -> Result<Vec<u8>, RuntimeError> {
+        Compiled::new(program).run(io::Cursor::new(input.to_vec()), options)
+    }
+
+    #[test]
+    fn test_reuse() {
+        let compiled = Compiled::new(&Program::parse(HELLO).unwrap());
+        assert_eq!(compiled.run(io::empty(), Options::default()).unwrap(), b"Hello, world!\n");
+        assert_eq!(compiled.run(io::empty(), Options::default()).unwrap(), b"Hello, world!\n");
+        assert!(Compiled::new(&Program::parse("").unwrap()).run(io::empty(), Options::default()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_byte_fallbacks() {
+        // The fast paths fall back to the parts of the translation around
+        // each bracket.
+        for seed in 0 .. 100 {
+            let program = Program::parse(&disturbed_brainfuck(seed, 30)).unwrap();
+            if run(program.clone(), b"ab", 200_000).is_some() {
+                for tape_limit in [None, Some(30), Some(60)] {
+                    assert_same_output(&compiled, &program, b"ab", Options { tape_limit, ..Options::default() });
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_matches_interpreter() {
+        assert_engine_matches(&compiled);
+    }
+}
@@ -0,0 +1,187 @@
+use std::collections::VecDeque;
+use std::io::{self, BufReader, Read};
+use super::*;
+
+/// The bits available to `,`: a queue of bits already decoded, refilled a
+/// byte at a time from an optional reader.
+pub(crate) struct Input {
+    bits: VecDeque<Bit>,
+    source: Option<Box<dyn Read + Send>>,
+    closed: bool
+}
+
+impl Input {
+    pub fn from_bits(bits: Vec<Bit>) -> Input {
+        Input {
+            bits: bits.into(),
+            source: None,
+            closed: false
+        }
+    }
+
+    pub fn from_reader(reader: impl Read + Send + 'static) -> Input {
+        Input {
+            bits: VecDeque::new(),
+            source: Some(Box::new(BufReader::new(reader))),
+            closed: false
+        }
+    }
+
+    /// Queues more bits behind those already decoded, and so ahead of
+    /// the bytes the reader has yet to give.
+    pub fn feed(&mut self, bits: impl IntoIterator<Item = Bit>) {
+        self.bits.extend(bits);
+    }
+
+    /// Records that no more input will be fed.
+    pub fn close(&mut self) {
+        self.closed = true;
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    /// The next input bit, or `None` if the queue is empty and the reader
+    /// is at end of file. A reader that would block fails with
+    /// [`io::ErrorKind::WouldBlock`], which is not the end of the input.
+    pub fn read_bit(&mut self, order: BitOrder) -> io::Result<Option<Bit>> {
+        if self.bits.is_empty() {
+            if let Some(byte) = self.read_byte()? {
+                self.bits.extend(order.bits_from_u8(byte));
+            }
+        }
+
+        Ok(self.bits.pop_front())
+    }
+
+    /// Whether `count` bits can be read without running out or blocking,
+    /// decoding them from the reader ahead of time if need be.
+    pub fn has_bits(&mut self, count: usize, order: BitOrder) -> io::Result<bool> {
+        while self.bits.len() < count {
+            match self.read_byte() {
+                Ok(Some(byte)) => self.bits.extend(order.bits_from_u8(byte)),
+                Ok(None) => return Ok(false),
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+                Err(e) => return Err(e)
+            }
+        }
+
+        Ok(true)
+    }
+
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        let source = match &mut self.source {
+            Some(source) => source,
+            None => return Ok(None)
+        };
+
+        let mut byte = [0];
+        loop {
+            match source.read(&mut byte) {
+                Ok(0) => return Ok(None),
+                Ok(_) => return Ok(Some(byte[0])),
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+                Err(e) => return Err(e)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_all(input: &mut Input) -> Vec<Bit> {
+        let mut bits = vec![];
+        while let Some(bit) = input.read_bit(BitOrder::LsbFirst).unwrap() {
+            bits.push(bit);
+        }
+
+        bits
+    }
+
+    #[test]
+    fn test_from_bits() {
+        use Bit::*;
+        let mut input = Input::from_bits(vec![One, Zero, One]);
+        assert_eq!(read_all(&mut input), vec![One, Zero, One]);
+        assert_eq!(input.read_bit(BitOrder::LsbFirst).unwrap(), None);
+    }
+
+    #[test]
+    fn test_from_reader() {
+        let mut input = Input::from_reader(io::Cursor::new(vec![1, 2, 3]));
+        assert_eq!(read_all(&mut input), utils::from_bytes(&[1, 2, 3]));
+        assert_eq!(input.read_bit(BitOrder::LsbFirst).unwrap(), None);
+    }
+
+    #[test]
+    fn test_msb_first() {
+        let mut input = Input::from_reader(io::Cursor::new(vec![1]));
+        let mut bits = vec![];
+        while let Some(bit) = input.read_bit(BitOrder::MsbFirst).unwrap() {
+            bits.push(bit);
+        }
+
+        assert_eq!(bits, BitOrder::MsbFirst.bits_from_u8(1));
+    }
+
+    #[test]
+    fn test_feed() {
+        use Bit::*;
+        // Fed bits come before the bytes the reader has not given yet...
+        let mut input = Input::from_reader(io::Cursor::new(vec![255]));
+        input.feed(vec![Zero]);
+        assert_eq!(read_all(&mut input), vec![Zero, One, One, One, One, One, One, One, One]);
+
+        // ...but after the rest of a byte already taken from it.
+        let mut input = Input::from_reader(io::Cursor::new(vec![255]));
+        assert_eq!(input.read_bit(BitOrder::LsbFirst).unwrap(), Some(One));
+        input.feed(vec![Zero]);
+        assert_eq!(read_all(&mut input), vec![One, One, One, One, One, One, One, Zero]);
+        input.feed(utils::bits_from_u8(2));
+        assert_eq!(read_all(&mut input), utils::bits_from_u8(2));
+    }
+
+    #[test]
+    fn test_has_bits() {
+        let mut input = Input::from_reader(io::Cursor::new(vec![3, 4]));
+        input.feed(vec![Bit::One]);
+        assert!(input.has_bits(17, BitOrder::LsbFirst).unwrap());
+        assert!(!input.has_bits(18, BitOrder::LsbFirst).unwrap());
+        assert_eq!(input.read_bit(BitOrder::LsbFirst).unwrap(), Some(Bit::One));
+        assert_eq!(read_all(&mut input), utils::from_bytes(&[3, 4]));
+    }
+
+    #[test]
+    fn test_would_block() {
+        struct Blocking;
+
+        impl Read for Blocking {
+            fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+                Err(io::ErrorKind::WouldBlock.into())
+            }
+        }
+
+        let mut input = Input::from_reader(Blocking);
+        assert_eq!(input.read_bit(BitOrder::LsbFirst).unwrap_err().kind(), io::ErrorKind::WouldBlock);
+        assert!(!input.has_bits(1, BitOrder::LsbFirst).unwrap());
+        input.feed(vec![Bit::One]);
+        assert_eq!(input.read_bit(BitOrder::LsbFirst).unwrap(), Some(Bit::One));
+    }
+
+    #[test]
+    fn test_reader_error() {
+        struct Failing;
+
+        impl Read for Failing {
+            fn read(&mut self, _: &mut [u8]) -> io::Result<usize> {
+                Err(io::Error::other("broken pipe"))
+            }
+        }
+
+        let mut input = Input::from_reader(Failing);
+        assert_eq!(input.read_bit(BitOrder::LsbFirst).unwrap_err().to_string(), "broken pipe");
+    }
+}
@@ -0,0 +1,503 @@
+use std::collections::HashMap;
+use super::*;
+
+/// An instruction of the optimised form that the interpreter executes.
+#[derive (PartialEq, Eq, Copy, Clone, Debug)]
+pub enum Op {
+    Flip,
+    Read,
+    Write,
+    /// Moves the pointer `by` cells, to the right if positive. On the
+    /// way it reaches as far as `low` and `high` cells from where it
+    /// started, which count as visited and against the tape limit.
+    Move { by: isize, low: isize, high: isize },
+    /// `[`: jumps to the matching `SkipLeft` at this index if the cell
+    /// under the pointer is zero.
+    SkipRight(usize),
+    /// `]`: jumps back to the matching `SkipRight` at this index if the
+    /// cell under the pointer is one.
+    SkipLeft(usize),
+    /// `[+]`: clears the cell under the pointer.
+    SetZero,
+    /// `[>]`: moves the pointer right to the nearest zero cell.
+    ScanRight,
+    /// `[<]`: moves the pointer left to the nearest zero cell.
+    ScanLeft,
+    /// The translation of Brainfuck `+`: increments the byte right of the
+    /// pointer. Like the other byte ops, it is followed by the given number
+    /// of ops spelling out the translation, which run instead whenever the
+    /// cells around the byte are not as the translation expects. A backend
+    /// without a fast path for a byte op can ignore it and always run the
+    /// translation.
+    IncByte(usize),
+    /// The translation of Brainfuck `-`: decrements the byte right of the
+    /// pointer.
+    DecByte(usize),
+    /// The translation of Brainfuck `,`: reads eight bits into the byte
+    /// right of the pointer.
+    ReadByte(usize),
+    /// The translation of Brainfuck `.`: writes the byte right of the
+    /// pointer.
+    WriteByte(usize),
+    /// The translation of Brainfuck `[`: skips past the matching
+    /// `ByteLoopEnd`, at the second index, and its translation if the byte
+    /// is zero.
+    ByteLoopStart(usize, usize),
+    /// The translation of Brainfuck `]`: jumps back past the matching
+    /// `ByteLoopStart`, at the second index, and its translation unless the
+    /// byte is zero.
+    ByteLoopEnd(usize, usize)
+}
+
+impl Op {
+    /// A `Move` that goes `by` cells without turning back.
+    pub fn straight(by: isize) -> Op {
+        Op::Move { by, low: by.min(0), high: by.max(0) }
+    }
+}
+
+/// Translates each instruction into exactly one op. Running the result
+/// is the reference semantics the optimised form is checked against.
+///
+/// Like [`optimize`], also returns the index of the instruction each op
+/// came from.
+pub fn lower(instructions: &[Instruction]) -> (Vec<Op>, Vec<usize>) {
+    let mut ops: Vec<_> = instructions.iter().map(|instruction| lower_instruction(*instruction)).collect();
+    link(&mut ops);
+    (ops, (0 .. instructions.len()).collect())
+}
+
+/// Translates instructions into ops, folding runs of moves into a single
+/// `Move`, dropping flips that cancel out, replacing loop idioms
+/// with dedicated ops, dropping loops that can never be entered and
+/// running translated Brainfuck a byte at a time.
+///
+/// Also returns the index of the instruction each op came from: the
+/// first of a folded run, the `[` of a loop idiom and the start of a
+/// Brainfuck macro.
+pub fn optimize(instructions: &[Instruction]) -> (Vec<Op>, Vec<usize>) {
+    let matches = bracket_matches(instructions);
+    let blocks = byte_blocks(instructions, &matches);
+    let (mut ops, sources): (Vec<_>, Vec<_>) = fold(instructions, &matches, &blocks).into_iter().unzip();
+    link(&mut ops);
+    (ops, sources)
+}
+
+/// Points each bracket and byte loop op at the op it pairs with.
+fn link(ops: &mut [Op]) {
+    let mut open = vec![];
+    // Byte loops nest among themselves, interleaved with the brackets of
+    // their own translations, so they are paired separately.
+    let mut open_loops = vec![];
+
+    for i in 0 .. ops.len() {
+        match ops[i] {
+            Op::SkipRight(_) => open.push(i),
+            Op::ByteLoopStart(..) => open_loops.push(i),
+            Op::SkipLeft(_) => {
+                let prev_i = open.pop().expect("brackets are checked before linking");
+                ops[prev_i] = Op::SkipRight(i);
+                ops[i] = Op::SkipLeft(prev_i);
+            },
+            Op::ByteLoopEnd(len, _) => {
+                let prev_i = open_loops.pop().expect("byte loops come in pairs");
+                if let Op::ByteLoopStart(prev_len, _) = ops[prev_i] {
+                    ops[prev_i] = Op::ByteLoopStart(prev_len, i);
+                }
+                ops[i] = Op::ByteLoopEnd(len, prev_i);
+            },
+            _ => {}
+        }
+    }
+}
+
+/// The op just past the translation that follows the byte op at `i`.
+pub(crate) fn past_translation(ops: &[Op], i: usize) -> usize {
+    match ops[i] {
+        Op::IncByte(len) | Op::DecByte(len) | Op::ReadByte(len) | Op::WriteByte(len)
+            | Op::ByteLoopStart(len, _) | Op::ByteLoopEnd(len, _) => i + 1 + len,
+        op => unreachable!("{:?} is not a byte op", op)
+    }
+}
+
+/// Moves ops that were linked on their own to start at index `offset`.
+pub(crate) fn relocate(ops: &mut [Op], offset: usize) {
+    for op in ops {
+        *op = match *op {
+            Op::SkipRight(target) => Op::SkipRight(target + offset),
+            Op::SkipLeft(target) => Op::SkipLeft(target + offset),
+            Op::ByteLoopStart(len, target) => Op::ByteLoopStart(len, target + offset),
+            Op::ByteLoopEnd(len, target) => Op::ByteLoopEnd(len, target + offset),
+            op => op
+        };
+    }
+}
+
+/// Optimises everything but the byte blocks in `blocks`, which become
+/// byte ops. Each op comes with the index of its instruction.
+fn fold(instructions: &[Instruction], matches: &[usize], blocks: &HashMap<usize, (usize, Block)>) -> Vec<(Op, usize)> {
+    let mut ops = vec![];
+    // Ops before `fixed` belong to a byte block, whose length is baked
+    // into its first op, so they must not be merged with what follows.
+    let mut fixed = 0;
+    let mut i = 0;
+
+    while i < instructions.len() {
+        if let Some(&(len, block)) = blocks.get(&i) {
+            let literal = block_literal(&instructions[i .. i + len], block);
+            ops.push((block.op(literal.len()), i));
+            ops.extend(literal.into_iter().map(|(op, source)| (op, source + i)));
+            fixed = ops.len();
+            i += len;
+            continue;
+        }
+
+        let op = lower_instruction(instructions[i]);
+
+        // The cell is known to be zero here, so the loop is skipped.
+        if matches!(op, Op::SkipRight(_)) && ops.len() > fixed && ops.last().is_some_and(|(op, _)| leaves_zero(op)) {
+            i = matches[i] + 1;
+            continue;
+        }
+
+        push(&mut ops, fixed, (op, i));
+        i += 1;
+    }
+
+    ops
+}
+
+/// A Brainfuck macro that `optimize` runs as a single byte op.
+#[derive (PartialEq, Eq, Copy, Clone, Debug)]
+enum Block {
+    Inc,
+    Dec,
+    Read,
+    Write,
+    LoopStart,
+    LoopEnd
+}
+
+impl Block {
+    const SIMPLE: [(Block, char); 4] = [(Block::Inc, '+'), (Block::Dec, '-'), (Block::Read, ','), (Block::Write, '.')];
+
+    fn op(self, len: usize) -> Op {
+        match self {
+            Block::Inc => Op::IncByte(len),
+            Block::Dec => Op::DecByte(len),
+            Block::Read => Op::ReadByte(len),
+            Block::Write => Op::WriteByte(len),
+            Block::LoopStart => Op::ByteLoopStart(len, 0),
+            Block::LoopEnd => Op::ByteLoopEnd(len, 0)
+        }
+    }
+}
+
+/// Finds the Brainfuck macros in `instructions`, by the index they start
+/// at, with their length. Loop macros only count in pairs whose brackets
+/// match each other.
+fn byte_blocks(instructions: &[Instruction], matches: &[usize]) -> HashMap<usize, (usize, Block)> {
+    let simple: Vec<_> = Block::SIMPLE.iter()
+        .map(|&(block, command)| (block, macro_instructions(command)))
+        .collect();
+    let start = macro_instructions('[');
+    let end = macro_instructions(']');
+    // Where the bracket sits within the loop macros.
+    let start_bracket = unmatched_bracket(&start);
+    let end_bracket = unmatched_bracket(&end);
+
+    let mut blocks = HashMap::new();
+    let mut pending = HashMap::new();
+    let mut i = 0;
+
+    while i < instructions.len() {
+        let rest = &instructions[i ..];
+
+        if let Some(start_i) = pending.remove(&i) {
+            blocks.insert(start_i, (start.len(), Block::LoopStart));
+            blocks.insert(i, (end.len(), Block::LoopEnd));
+            i += end.len();
+            continue;
+        }
+
+        if rest.starts_with(&start) {
+            let end_i = matches[i + start_bracket] - end_bracket;
+            if end_i >= i + start.len() && instructions[end_i ..].starts_with(&end) {
+                pending.insert(end_i, i);
+                i += start.len();
+                continue;
+            }
+        }
+
+        match simple.iter().find(|(_, code)| rest.starts_with(code)) {
+            Some((block, code)) => {
+                blocks.insert(i, (code.len(), *block));
+                i += code.len();
+            },
+            None => i += 1
+        }
+    }
+
+    blocks
+}
+
+/// The ops that spell out a byte block, run when its fast path does not
+/// apply. The halves of a loop macro are unbalanced on their own, so the
+/// parts around its bracket are optimised separately.
+fn block_literal(instructions: &[Instruction], block: Block) -> Vec<(Op, usize)> {
+    if !matches!(block, Block::LoopStart | Block::LoopEnd) {
+        return literal(instructions);
+    }
+
+    let at = unmatched_bracket(instructions);
+    let mut ops = literal(&instructions[.. at]);
+    ops.push((lower_instruction(instructions[at]), at));
+    ops.extend(literal(&instructions[at + 1 ..]).into_iter().map(|(op, source)| (op, source + at + 1)));
+    ops
+}
+
+/// Optimises balanced `instructions` without looking for byte blocks.
+fn literal(instructions: &[Instruction]) -> Vec<(Op, usize)> {
+    fold(instructions, &bracket_matches(instructions), &HashMap::new())
+}
+
+/// The index of the one bracket in `instructions` without a match.
+fn unmatched_bracket(instructions: &[Instruction]) -> usize {
+    let mut open = vec![];
+
+    for (i, instruction) in instructions.iter().enumerate() {
+        match instruction {
+            Instruction::SkipRight => open.push(i),
+            Instruction::SkipLeft if open.pop().is_none() => return i,
+            _ => {}
+        }
+    }
+
+    open.pop().expect("one bracket is unmatched")
+}
+
+fn macro_instructions(command: char) -> Vec<Instruction> {
+    brainfuck::translation(command).chars().filter_map(parser::parse_instruction).collect()
+}
+
+/// Appends `op` and its source, merging it with the ops from index
+/// `fixed` on. A merged op keeps the source of the first op merged.
+fn push(ops: &mut Vec<(Op, usize)>, fixed: usize, (op, source): (Op, usize)) {
+    if let Op::SkipLeft(_) = op {
+        if let [.., (Op::SkipRight(_), start), (body, _)] = ops[fixed ..] {
+            if let Some(idiom) = loop_idiom(body) {
+                ops.truncate(ops.len() - 2);
+                return push(ops, fixed, (idiom, start));
+            }
+        }
+    }
+
+    let last = if ops.len() > fixed { ops.last_mut() } else { None };
+    match (op, last) {
+        (Op::Flip, Some((Op::Flip, _))) => {
+            ops.pop();
+        },
+        // Moves that cancel out still keep their reach.
+        (Op::Move { by, low, high }, Some((Op::Move { by: n, low: n_low, high: n_high }, _))) => {
+            *n_low = (*n_low).min(*n + low);
+            *n_high = (*n_high).max(*n + high);
+            *n += by;
+        },
+        (Op::SetZero, Some((Op::Flip, _))) | (Op::SetZero, Some((Op::SetZero, _))) => {
+            ops.pop();
+            push(ops, fixed, (op, source));
+        },
+        (op, _) => ops.push((op, source))
+    }
+}
+
+/// The op equivalent to a loop whose whole body is `body`.
+fn loop_idiom(body: Op) -> Option<Op> {
+    match body {
+        Op::Flip | Op::SetZero => Some(Op::SetZero),
+        Op::Move { by: 1, low: 0, high: 1 } | Op::ScanRight => Some(Op::ScanRight),
+        Op::Move { by: -1, low: -1, high: 0 } | Op::ScanLeft => Some(Op::ScanLeft),
+        _ => None
+    }
+}
+
+/// Whether the cell under the pointer is always zero after `op`.
+fn leaves_zero(op: &Op) -> bool {
+    matches!(op, Op::SkipLeft(_) | Op::SetZero | Op::ScanRight | Op::ScanLeft)
+}
+
+fn bracket_matches(instructions: &[Instruction]) -> Vec<usize> {
+    let mut matches = vec![0; instructions.len()];
+    let mut open = vec![];
+
+    for (i, instruction) in instructions.iter().enumerate() {
+        match instruction {
+            Instruction::SkipRight => open.push(i),
+            Instruction::SkipLeft => {
+                let prev_i = open.pop().expect("brackets are checked before optimising");
+                matches[prev_i] = i;
+                matches[i] = prev_i;
+            },
+            _ => {}
+        }
+    }
+
+    matches
+}
+
+fn lower_instruction(instruction: Instruction) -> Op {
+    match instruction {
+        Instruction::Flip => Op::Flip,
+        Instruction::Read => Op::Read,
+        Instruction::Write => Op::Write,
+        Instruction::MoveLeft => Op::straight(-1),
+        Instruction::MoveRight => Op::straight(1),
+        Instruction::SkipRight => Op::SkipRight(0),
+        Instruction::SkipLeft => Op::SkipLeft(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testing::*;
+
+    fn optimized(code: &str) -> Vec<Op> {
+        optimize(&parser::parse(code).unwrap()).0
+    }
+
+    #[test]
+    fn test_lower() {
+        use Instruction::*;
+        assert_eq!(
+            lower(&[Flip, Flip, MoveRight, MoveLeft, Read, Write, SkipRight, SkipLeft]).0,
+            vec![Op::Flip, Op::Flip, Op::straight(1), Op::straight(-1), Op::Read, Op::Write, Op::SkipRight(7), Op::SkipLeft(6)]
+        );
+    }
+
+    #[test]
+    fn test_fold_moves() {
+        assert_eq!(optimized(">>>>>>>>"), vec![Op::straight(8)]);
+        assert_eq!(optimized("<<<;>>"), vec![Op::straight(-3), Op::Write, Op::straight(2)]);
+        assert_eq!(optimized(">><<<"), vec![Op::Move { by: -1, low: -1, high: 2 }]);
+        assert_eq!(optimized("<>>><"), vec![Op::Move { by: 1, low: -1, high: 2 }]);
+    }
+
+    #[test]
+    fn test_cancel() {
+        assert_eq!(optimized("++"), vec![]);
+        assert_eq!(optimized("+++"), vec![Op::Flip]);
+        assert_eq!(optimized(">++>"), vec![Op::straight(2)]);
+
+        // Moves that cancel out are kept for the cells they visit.
+        assert_eq!(optimized("<>"), vec![Op::Move { by: 0, low: -1, high: 0 }]);
+        assert_eq!(optimized("><;"), vec![Op::Move { by: 0, low: 0, high: 1 }, Op::Write]);
+        assert_eq!(optimized("+<>+"), vec![Op::Flip, Op::Move { by: 0, low: -1, high: 0 }, Op::Flip]);
+    }
+
+    #[test]
+    fn test_keeps_structure() {
+        assert_eq!(
+            optimized("[;+]+[,]"),
+            vec![Op::SkipRight(3), Op::Write, Op::Flip, Op::SkipLeft(0), Op::Flip, Op::SkipRight(7), Op::Read, Op::SkipLeft(5)]
+        );
+        assert_eq!(optimized("[>>]"), vec![Op::SkipRight(2), Op::straight(2), Op::SkipLeft(0)]);
+        assert_eq!(optimized("[]"), vec![Op::SkipRight(1), Op::SkipLeft(0)]);
+    }
+
+    #[test]
+    fn test_loop_idioms() {
+        assert_eq!(optimized("[+]"), vec![Op::SetZero]);
+        assert_eq!(optimized("[+++]"), vec![Op::SetZero]);
+        assert_eq!(optimized("[>]"), vec![Op::ScanRight]);
+        assert_eq!(optimized("[<]"), vec![Op::ScanLeft]);
+        // A scan would not visit the cell past the zero.
+        assert_eq!(optimized("[>><]"), vec![Op::SkipRight(2), Op::Move { by: 1, low: 0, high: 2 }, Op::SkipLeft(0)]);
+        assert_eq!(optimized("[[+]]"), vec![Op::SetZero]);
+        assert_eq!(optimized("[[<]]"), vec![Op::ScanLeft]);
+        assert_eq!(optimized(">[>]<"), vec![Op::straight(1), Op::ScanRight, Op::straight(-1)]);
+    }
+
+    #[test]
+    fn test_no_ops() {
+        assert_eq!(optimized("+[+]"), vec![Op::SetZero]);
+        assert_eq!(optimized("[+]+[+]"), vec![Op::SetZero]);
+        assert_eq!(optimized("[+][;]"), vec![Op::SetZero]);
+        assert_eq!(optimized("[>][+[,]]>"), vec![Op::ScanRight, Op::straight(1)]);
+        assert_eq!(optimized("[;][;]"), vec![Op::SkipRight(2), Op::Write, Op::SkipLeft(0)]);
+    }
+
+    /// The ops of the macro for `command`, checking that they are `op`
+    /// followed by the translation it skips.
+    fn byte_block(command: char, op: fn(usize) -> Op) -> Vec<Op> {
+        let ops = optimized(brainfuck::translation(command));
+        assert_eq!(ops[0], op(ops.len() - 1));
+        ops
+    }
+
+    #[test]
+    fn test_byte_blocks() {
+        byte_block('+', Op::IncByte);
+        byte_block('-', Op::DecByte);
+        byte_block(',', Op::ReadByte);
+        byte_block('.', Op::WriteByte);
+        assert_eq!(optimized(brainfuck::translation('>')), vec![Op::straight(9)]);
+
+        // The translation is not merged with the code around it.
+        let ops = optimized(&(brainfuck::translation('+').to_string() + ">"));
+        assert_eq!(ops[.. ops.len() - 1], byte_block('+', Op::IncByte)[..]);
+        assert_eq!(ops.last(), Some(&Op::straight(1)));
+    }
+
+    #[test]
+    fn test_byte_loops() {
+        let ops = optimized(&brainfuck::translate("[-]").unwrap());
+        let end = ops.iter().position(|op| matches!(op, Op::ByteLoopEnd(..))).unwrap();
+        let start = match ops[0] {
+            Op::ByteLoopStart(len, target) if target == end => len,
+            op => panic!("{:?} is not a byte loop ending at {}", op, end)
+        };
+        assert!(matches!(ops[start + 1], Op::DecByte(_)));
+        assert_eq!(ops[end], Op::ByteLoopEnd(ops.len() - end - 1, 0));
+
+        // A loop macro whose bracket is not closed by the other loop macro
+        // is left as it is.
+        let code = format!("{}]", brainfuck::translation('['));
+        assert!(!optimized(&code).iter().any(|op| matches!(op, Op::ByteLoopStart(..))));
+    }
+
+    #[test]
+    fn test_brainfuck_matches_reference() {
+        for seed in 0 .. 300 {
+            let instructions = parser::parse(&brainfuck::translate(&random_brainfuck(seed, 30)).unwrap()).unwrap();
+            assert_same_behaviour(&instructions, Program::new(instructions.clone()).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_disturbed_brainfuck_matches_reference() {
+        for seed in 0 .. 300 {
+            let instructions = parser::parse(&disturbed_brainfuck(seed, 30)).unwrap();
+            assert_same_behaviour(&instructions, Program::new(instructions.clone()).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_matches_reference() {
+        for seed in 0 .. 500 {
+            let instructions = random_program(seed, 40);
+            assert_same_behaviour(&instructions, Program::new(instructions.clone()).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_idioms_match_reference() {
+        let fragments = ["[+]", "+[+]", "[>]", "[<]", "[[>]]", "[]", "+", ">", "<", ">>", ",", ";"];
+        for seed in 0 .. 500 {
+            let mut rng = Rng::new(seed);
+            let code: String = (0 .. 12).map(|_| fragments[rng.below(fragments.len())]).collect();
+            let instructions = parser::parse(&code).unwrap();
+            assert_same_behaviour(&instructions, Program::new(instructions.clone()).unwrap());
+        }
+    }
+}
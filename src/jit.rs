@@ -0,0 +1,399 @@
+//! Compiling programs to x86-64 machine code.
+//!
+//! Only available on x86-64 Linux with the `jit` feature. The ops of a
+//! [`Program`] are translated one by one into a function kept in an
+//! executable mapping. The tape is packed 64 cells to a word. Flips, tests
+//! and jumps run inline. Moves check the pointer against the visited
+//! cells and call back into Rust to grow the tape and enforce the tape
+//! limit, and `,` and `;` call back into Rust for their I/O, so a run
+//! behaves like [`Interpreter::interpret`].
+//!
+//! ```
+//! # #[cfg(all(feature = "jit", target_arch = "x86_64", target_os = "linux"))] {
+//! use boolfuck::{jit::Jit, Options, Program};
+//!
+//! let jit = Jit::compile(&Program::parse(",;,;,;,;,;,;,;,;").unwrap()).unwrap();
+//! assert_eq!(jit.run(std::io::Cursor::new(vec![0x5a]), Options::default()).unwrap(), [0x5a]);
+//! # }
+//! ```
+
+use std::ffi::c_void;
+use std::io::Read;
+use std::mem::{self, offset_of};
+use std::ptr;
+use super::*;
+use input::Input;
+use output::Output;
+
+extern "C" {
+    fn mmap(addr: *mut c_void, len: usize, prot: i32, flags: i32, fd: i32, offset: i64) -> *mut c_void;
+    fn mprotect(addr: *mut c_void, len: usize, prot: i32) -> i32;
+    fn munmap(addr: *mut c_void, len: usize) -> i32;
+}
+
+const PROT_READ: i32 = 1;
+const PROT_WRITE: i32 = 2;
+const PROT_EXEC: i32 = 4;
+const MAP_PRIVATE: i32 = 2;
+const MAP_ANONYMOUS: i32 = 0x20;
+
+/// A program compiled to machine code, ready to run any number of times.
+pub struct Jit {
+    code: *mut c_void,
+    len: usize
+}
+
+/// The state of a run. The machine code keeps the pointer, the tape
+/// words and the visited cells in registers, loading them from the first
+/// fields whenever a callback may have moved them.
+#[repr(C)]
+struct Context {
+    /// The first word of `tape`.
+    words: *mut u64,
+    /// The index of the cell under the pointer among the bits of `tape`.
+    pointer: isize,
+    /// The index of the leftmost visited cell among the bits of `tape`.
+    low: isize,
+    /// How many cells have been visited.
+    span: usize,
+    tape: Vec<u64>,
+    input: Input,
+    output: Output,
+    options: Options,
+    error: Option<RuntimeError>
+}
+
+impl Context {
+    fn get(&self, index: isize) -> Bit {
+        let index = index as usize;
+        if self.tape[index / 64] >> (index % 64) & 1 == 1 { Bit::One } else { Bit::Zero }
+    }
+
+    fn set(&mut self, index: isize, bit: Bit) {
+        let index = index as usize;
+        let mask = 1 << (index % 64);
+        match bit {
+            Bit::One => self.tape[index / 64] |= mask,
+            Bit::Zero => self.tape[index / 64] &= !mask
+        }
+    }
+
+    fn fail(&mut self, error: RuntimeError) -> i32 {
+        self.error = Some(error);
+        1
+    }
+}
+
+/// Visits the cell at `index`, which lies outside the visited cells,
+/// growing the tape if need be. Returns the index of the cell once the
+/// tape has grown, or -1 if the tape limit forbids the visit.
+extern "C" fn visit(context: *mut Context, mut index: isize) -> isize {
+    let context = unsafe { &mut *context };
+    let mut low = context.low.min(index);
+    let high = (context.low + context.span as isize - 1).max(index);
+    let span = (high - low + 1) as usize;
+
+    if let Err(e) = tape::check_limit(span, context.options.tape_limit) {
+        context.fail(e);
+        return -1;
+    }
+
+    if index < 0 {
+        let words = index.unsigned_abs().div_ceil(64);
+        let words = words.max(context.tape.len());
+        context.tape.splice(0 .. 0, (0 .. words).map(|_| 0));
+        index += words as isize * 64;
+        low += words as isize * 64;
+    } else if index as usize >= context.tape.len() * 64 {
+        let words = (index as usize / 64 + 1).max(2 * context.tape.len());
+        context.tape.resize(words, 0);
+    }
+
+    context.words = context.tape.as_mut_ptr();
+    context.pointer = index;
+    context.low = low;
+    context.span = span;
+    index
+}
+
+/// Runs `,` on the cell at `index`. Returns 0, or 1 if the run failed.
+extern "C" fn read(context: *mut Context, index: isize) -> i32 {
+    let context = unsafe { &mut *context };
+    let bit = match context.input.read_bit(context.options.bit_order) {
+        Ok(Some(bit)) => Ok(bit),
+        Ok(None) => context.options.eof.bit(context.get(index)),
+        Err(e) => Err(e.into())
+    };
+    let bit = match bit {
+        Ok(bit) => bit,
+        Err(e) => return context.fail(e)
+    };
+
+    context.set(index, bit);
+    0
+}
+
+/// Runs `;` on the cell at `index`. Returns 0, or 1 if the run failed.
+extern "C" fn write(context: *mut Context, index: isize) -> i32 {
+    let context = unsafe { &mut *context };
+    let bit = context.get(index);
+    match context.output.write_bit(bit, context.options.bit_order) {
+        Ok(()) => 0,
+        Err(e) => context.fail(e.into())
+    }
+}
+
+// The registers the compiled code keeps its state in.
+const POINTER: u8 = 12;
+const WORDS: u8 = 13;
+const LOW: u8 = 14;
+const SPAN: u8 = 15;
+
+/// Machine code under construction.
+struct Assembler {
+    code: Vec<u8>,
+    /// The offset of each op, then of the end and of the failure exit.
+    labels: Vec<usize>,
+    /// Where a 32-bit displacement to a label must be filled in.
+    fixups: Vec<(usize, usize)>
+}
+
+impl Assembler {
+    fn emit(&mut self, bytes: &[u8]) {
+        self.code.extend_from_slice(bytes);
+    }
+
+    /// `mov reg, [rbx + offset]` for one of r8 to r15.
+    fn load(&mut self, register: u8, offset: usize) {
+        self.emit(&[0x4c, 0x8b, 0x43 | (register - 8) << 3, offset as u8]);
+    }
+
+    /// `bt`, `btr` or `btc` of the cell under the pointer.
+    fn bit(&mut self, opcode: u8) {
+        self.emit(&[0x4d, 0x0f, opcode, 0x65, 0x00]);
+    }
+
+    /// A jump with a 32-bit displacement to the label `target`.
+    fn jump(&mut self, opcode: &[u8], target: usize) {
+        self.emit(opcode);
+        self.fixups.push((self.code.len(), target));
+        self.emit(&[0; 4]);
+    }
+
+    /// Calls `function` with the context and the pointer.
+    fn call(&mut self, function: *const ()) {
+        self.emit(&[0x48, 0x89, 0xdf]);
+        self.emit(&[0x4c, 0x89, 0xe6]);
+        self.emit(&[0x48, 0xb8]);
+        self.emit(&(function as u64).to_le_bytes());
+        self.emit(&[0xff, 0xd0]);
+    }
+
+    /// Moves the pointer by `by`, visiting the cells `low` and `high`
+    /// from where it starts on the way if they lie beyond both ends.
+    fn move_by(&mut self, by: isize, low: isize, high: isize, fail: usize) {
+        let mut at = 0;
+        for reach in [low, high] {
+            if reach < by.min(0) || reach > by.max(0) {
+                self.step(reach - at, fail);
+                at = reach;
+            }
+        }
+
+        self.step(by - at, fail);
+    }
+
+    /// Moves the pointer by `by`, calling [`visit`] if it leaves the
+    /// visited cells.
+    fn step(&mut self, by: isize, fail: usize) {
+        let mut left = by;
+        while left != 0 {
+            let step = left.clamp(i32::MIN as isize, i32::MAX as isize);
+            self.emit(&[0x49, 0x81, 0xc4]);
+            self.emit(&(step as i32).to_le_bytes());
+            left -= step;
+        }
+
+        // Unsigned, the pointer is inside the visited cells iff
+        // pointer - low < span.
+        self.emit(&[0x4c, 0x89, 0xe0, 0x4c, 0x29, 0xf0, 0x4c, 0x39, 0xf8]);
+        self.emit(&[0x72, 0]);
+        let skip = self.code.len();
+
+        self.call(visit as *const ());
+        self.emit(&[0x48, 0x85, 0xc0]);
+        self.jump(&[0x0f, 0x88], fail);
+        self.emit(&[0x49, 0x89, 0xc4]);
+        self.load(WORDS, offset_of!(Context, words));
+        self.load(LOW, offset_of!(Context, low));
+        self.load(SPAN, offset_of!(Context, span));
+
+        self.code[skip - 1] = (self.code.len() - skip) as u8;
+    }
+
+    /// `[>]` or `[<]` as a loop of single moves.
+    fn scan(&mut self, by: isize, fail: usize) {
+        let top = self.code.len();
+        self.bit(BT);
+        self.emit(&[0x0f, 0x83, 0, 0, 0, 0]);
+        let exit = self.code.len();
+        self.step(by, fail);
+        self.emit(&[0xe9]);
+        self.emit(&(top as i32 - self.code.len() as i32 - 4).to_le_bytes());
+        let end = self.code.len();
+        self.code[exit - 4 .. exit].copy_from_slice(&((end - exit) as i32).to_le_bytes());
+    }
+}
+
+const BT: u8 = 0xa3;
+const BTR: u8 = 0xb3;
+const BTC: u8 = 0xbb;
+
+/// Translates `ops` into a function taking a `*mut Context`, which
+/// returns 0 once the program halts or 1 if it failed.
+fn assemble(ops: &[Op]) -> Vec<u8> {
+    let end = ops.len();
+    let fail = end + 1;
+    let mut asm = Assembler { code: vec![], labels: vec![0; ops.len() + 2], fixups: vec![] };
+
+    // Save the callee-saved registers, which also aligns the stack for calls.
+    asm.emit(&[0x53, 0x41, 0x54, 0x41, 0x55, 0x41, 0x56, 0x41, 0x57]);
+    asm.emit(&[0x48, 0x89, 0xfb]);
+    asm.load(POINTER, offset_of!(Context, pointer));
+    asm.load(WORDS, offset_of!(Context, words));
+    asm.load(LOW, offset_of!(Context, low));
+    asm.load(SPAN, offset_of!(Context, span));
+
+    for (i, op) in ops.iter().enumerate() {
+        asm.labels[i] = asm.code.len();
+
+        match *op {
+            Op::Flip => asm.bit(BTC),
+            Op::Read | Op::Write => {
+                asm.call(if *op == Op::Read { read as *const () } else { write as *const () });
+                asm.emit(&[0x85, 0xc0]);
+                asm.jump(&[0x0f, 0x85], fail);
+            },
+            Op::Move { by, low, high } => asm.move_by(by, low, high, fail),
+            Op::SkipRight(target) => {
+                asm.bit(BT);
+                asm.jump(&[0x0f, 0x83], target);
+            },
+            Op::SkipLeft(target) => {
+                asm.bit(BT);
+                asm.jump(&[0x0f, 0x82], target);
+            },
+            Op::SetZero => asm.bit(BTR),
+            Op::ScanRight => asm.scan(1, fail),
+            Op::ScanLeft => asm.scan(-1, fail),
+            Op::IncByte(_) | Op::DecByte(_) | Op::ReadByte(_) | Op::WriteByte(_) | Op::ByteLoopStart(..) | Op::ByteLoopEnd(..) => {}
+        }
+    }
+
+    asm.labels[end] = asm.code.len();
+    asm.emit(&[0x31, 0xc0]);
+    asm.emit(&[0xeb, 5]);
+    asm.labels[fail] = asm.code.len();
+    asm.emit(&[0xb8, 1, 0, 0, 0]);
+    asm.emit(&[0x41, 0x5f, 0x41, 0x5e, 0x41, 0x5d, 0x41, 0x5c, 0x5b, 0xc3]);
+
+    for &(at, label) in &asm.fixups {
+        let displacement = asm.labels[label] as i32 - (at as i32 + 4);
+        asm.code[at .. at + 4].copy_from_slice(&displacement.to_le_bytes());
+    }
+
+    asm.code
+}
+
+impl Jit {
+    /// Compiles `program`. Fails only if the executable mapping cannot be
+    /// made.
+    pub fn compile(program: &Program) -> io::Result<Jit> {
+        let code = assemble(program.ops());
+        let len = code.len();
+
+        unsafe {
+            let mapping = mmap(ptr::null_mut(), len, PROT_READ | PROT_WRITE, MAP_PRIVATE | MAP_ANONYMOUS, -1, 0);
+            if mapping as isize == -1 {
+                return Err(io::Error::last_os_error());
+            }
+
+            ptr::copy_nonoverlapping(code.as_ptr(), mapping as *mut u8, len);
+            if mprotect(mapping, len, PROT_READ | PROT_EXEC) != 0 {
+                let error = io::Error::last_os_error();
+                munmap(mapping, len);
+                return Err(error);
+            }
+
+            Ok(Jit { code: mapping, len })
+        }
+    }
+
+    /// Runs the program with `options`, reading input from `reader`, and
+    /// returns the bytes it wrote.
+    pub fn run(&self, reader: impl Read + Send + 'static, options: Options) -> Result<Vec<u8>, RuntimeError> {
+        const ORIGIN: isize = 64;
+
+        let mut context = Context {
+            words: ptr::null_mut(),
+            pointer: ORIGIN,
+            low: ORIGIN,
+            span: 1,
+            tape: vec![0; 2],
+            input: Input::from_reader(reader),
+            output: Output::buffer(),
+            options,
+            error: None
+        };
+        context.words = context.tape.as_mut_ptr();
+
+        let function: extern "C" fn(*mut Context) -> i32 = unsafe { mem::transmute(self.code) };
+        if function(&mut context) != 0 {
+            return Err(context.error.take().expect("a failed run records its error"));
+        }
+
+        context.output.finish(options.partial_byte, options.bit_order)?;
+        Ok(context.output.bytes().to_vec())
+    }
+}
+
+// The mapping is never written once compiled.
+unsafe impl Send for Jit {}
+unsafe impl Sync for Jit {}
+
+impl Drop for Jit {
+    fn drop(&mut self) {
+        unsafe {
+            munmap(self.code, self.len);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testing::*;
+
+    fn jitted(program: &Program, input: &[u8], options: Options) -> Result<Vec<u8>, RuntimeError> {
+        Jit::compile(program).unwrap().run(io::Cursor::new(input.to_vec()), options)
+    }
+
+    #[test]
+    fn test_reuse() {
+        let jit = Jit::compile(&Program::parse(HELLO).unwrap()).unwrap();
+        assert_eq!(jit.run(io::empty(), Options::default()).unwrap(), b"Hello, world!\n");
+        assert_eq!(jit.run(io::empty(), Options::default()).unwrap(), b"Hello, world!\n");
+    }
+
+    #[test]
+    fn test_tape_growth() {
+        // Far enough both ways to move the tape in memory more than once.
+        let code = format!("{}+;>+;{}+;+[<]>;[>]{}+;", "<".repeat(700), ">".repeat(2000), ">".repeat(300));
+        assert_same_output(&jitted, &Program::parse(&code).unwrap(), b"", Options::default());
+    }
+
+    #[test]
+    fn test_matches_interpreter() {
+        assert_engine_matches(&jitted);
+    }
+}
@@ -1,35 +1,106 @@
-fn boolfuck(code: &str, input: Vec<u8>) -> Vec<u8> {
-    use interpreter::*;
-    use utils::*;
-    use parser::*;
-
-    let instructions = parse(code);
-    let input = from_bytes(&input);
-    let mut interpreter = Interpreter::new(instructions, input);
-    interpreter.interpret();
-    let output = interpreter.get_output();
-    to_bytes(output)
+//! Boolfuck interpreter.
+//!
+//! ```
+//! use boolfuck::{Interpreter, Program, utils};
+//!
+//! let program = Program::parse(";;;+;+;;+;+;+;+;+;+;;+;;+;;;+;;+;+;;+;;;+;;+;+;;+;+;;;;+;+;;+;;;+;;+;+;+;;;;;;;+;+;;+;;;+;+;;;+;+;;;;+;+;;+;;+;+;;+;;;+;;;+;;+;+;;+;;;+;+;;+;;+;+;+;;;;+;+;;;+;+;+;").unwrap();
+//! let mut interpreter = Interpreter::new(program, vec![]);
+//! interpreter.interpret().unwrap();
+//! assert_eq!(interpreter.get_output(), b"Hello, world!\n");
+//! ```
+
+use std::{fmt, io};
+
+pub mod brainfuck;
+pub mod bytecode;
+pub mod c;
+pub mod closures;
+mod input;
+mod ir;
+#[cfg(all(feature = "jit", target_arch = "x86_64", target_os = "linux"))]
+pub mod jit;
+mod output;
+mod program;
+pub mod rust;
+mod tape;
+pub mod wasm;
+#[cfg(test)]
+mod testing;
+
+pub use interpreter::{EofPolicy, Interpreter, Options, Status};
+pub use ir::Op;
+pub use output::PartialByte;
+pub use program::Program;
+pub use tape::Tape;
+pub use utils::BitOrder;
+
+/// Runs `code` on `input` and returns the bytes it wrote.
+///
+/// This is the codewars kata entry point: input and output bytes are
+/// converted to bits least significant bit first, and reading past the end
+/// of the input yields zero bits.
+pub fn boolfuck(code: &str, input: Vec<u8>) -> Result<Vec<u8>, Error> {
+    boolfuck_with(code, input, Options::default())
 }
 
-#[derive (PartialEq, Debug)]
+/// Runs `code` on `input` like [`boolfuck`], with the bit order, EOF
+/// behaviour and output padding taken from `options`.
+pub fn boolfuck_with(code: &str, input: Vec<u8>, options: Options) -> Result<Vec<u8>, Error> {
+    let program = Program::parse(code)?;
+    let mut interpreter = Interpreter::from_reader(program, io::Cursor::new(input)).with_options(options);
+    interpreter.interpret()?;
+    Ok(interpreter.get_output().to_vec())
+}
+
+/// A single Boolfuck command.
+#[derive (PartialEq, Eq, Copy, Clone, Debug)]
 pub enum Instruction {
+    /// `+` flips the bit under the pointer.
     Flip,
+    /// `,` reads one bit of input into the cell under the pointer.
     Read,
+    /// `;` writes the bit under the pointer to the output.
     Write,
+    /// `<` moves the pointer one cell to the left.
     MoveLeft,
+    /// `>` moves the pointer one cell to the right.
     MoveRight,
+    /// `[` jumps past the matching `]` if the bit under the pointer is zero.
     SkipRight,
+    /// `]` jumps back to the matching `[` if the bit under the pointer is one.
     SkipLeft
 }
 
-#[derive (PartialEq, Copy, Clone, Debug)]
+impl Instruction {
+    /// The character that spells this instruction in source.
+    pub fn to_char(self) -> char {
+        match self {
+            Self::Flip => '+',
+            Self::Read => ',',
+            Self::Write => ';',
+            Self::MoveLeft => '<',
+            Self::MoveRight => '>',
+            Self::SkipRight => '[',
+            Self::SkipLeft => ']'
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.to_char())
+    }
+}
+
+/// The value of a single tape cell or I/O bit.
+#[derive (PartialEq, Eq, Copy, Clone, Debug)]
 pub enum Bit {
     Zero,
     One
 }
 
 impl Bit {
-    fn flip(&self) -> Bit {
+    pub fn flip(&self) -> Bit {
         match self {
             Self::Zero => Self::One,
             Self::One => Self::Zero
@@ -37,9 +108,185 @@ impl Bit {
     }
 }
 
-mod utils {
+/// A location in Boolfuck source. `line` and `column` start at one.
+#[derive (PartialEq, Eq, Copy, Clone, Debug)]
+pub struct Position {
+    pub offset: usize,
+    pub line: usize,
+    pub column: usize
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
+/// A program whose brackets do not balance.
+#[derive (PartialEq, Eq, Clone, Debug)]
+pub enum ParseError {
+    /// A `[` without a matching `]`.
+    UnmatchedOpen(Position),
+    /// A `]` without a matching `[`.
+    UnmatchedClose(Position)
+}
+
+impl ParseError {
+    pub fn position(&self) -> Position {
+        match self {
+            Self::UnmatchedOpen(position) | Self::UnmatchedClose(position) => *position
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::UnmatchedOpen(position) => write!(f, "unmatched '[' at {}", position),
+            Self::UnmatchedClose(position) => write!(f, "unmatched ']' at {}", position)
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// A failure while a program is running.
+#[derive (Debug)]
+pub enum RuntimeError {
+    /// Reading input or writing output failed.
+    Io(io::Error),
+    /// The program halted with this many bits of an unfinished output byte
+    /// under [`PartialByte::Error`].
+    PartialByte(usize),
+    /// A `,` found the input exhausted under [`EofPolicy::Error`].
+    EndOfInput,
+    /// A move would make the tape span more cells than
+    /// [`Options::tape_limit`] allows.
+    TapeLimit(usize)
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "I/O error: {}", e),
+            Self::PartialByte(bits) => write!(f, "halted with {} bits of an unfinished output byte", bits),
+            Self::EndOfInput => write!(f, "read past the end of the input"),
+            Self::TapeLimit(limit) => write!(f, "tape grew past {} cells", limit)
+        }
+    }
+}
+
+impl std::error::Error for RuntimeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            _ => None
+        }
+    }
+}
+
+impl From<io::Error> for RuntimeError {
+    fn from(e: io::Error) -> RuntimeError {
+        RuntimeError::Io(e)
+    }
+}
+
+/// Any failure of [`boolfuck`].
+#[derive (Debug)]
+pub enum Error {
+    Parse(ParseError),
+    Runtime(RuntimeError)
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Parse(e) => e.fmt(f),
+            Self::Runtime(e) => e.fmt(f)
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Parse(e) => Some(e),
+            Self::Runtime(e) => Some(e)
+        }
+    }
+}
+
+impl From<ParseError> for Error {
+    fn from(e: ParseError) -> Error {
+        Error::Parse(e)
+    }
+}
+
+impl From<RuntimeError> for Error {
+    fn from(e: RuntimeError) -> Error {
+        Error::Runtime(e)
+    }
+}
+
+/// Conversions between bytes and bits. The free functions put the least
+/// significant bit first; [`BitOrder`] offers both orders.
+pub mod utils {
     use super::*;
 
+    /// The order in which the bits of a byte are read and written.
+    #[derive (PartialEq, Eq, Copy, Clone, Debug, Default)]
+    pub enum BitOrder {
+        /// Least significant bit first, as the kata requires.
+        #[default]
+        LsbFirst,
+        /// Most significant bit first.
+        MsbFirst
+    }
+
+    impl BitOrder {
+        pub fn from_bytes(self, v: &[u8]) -> Vec<Bit> {
+            v
+                .iter()
+                .flat_map(|num| self.bits_from_u8(*num))
+                .collect()
+        }
+
+        pub fn to_bytes(self, v: &[Bit]) -> Vec<u8> {
+            v
+                .chunks(8)
+                .map(|chunk| self.u8_from_bits(chunk))
+                .collect()
+        }
+
+        pub fn bits_from_u8(self, num: u8) -> Vec<Bit> {
+            let mut res = bits_from_u8(num);
+            if self == BitOrder::MsbFirst {
+                res.reverse();
+            }
+
+            res
+        }
+
+        /// Packs up to eight bits into a byte. Missing bits are the last
+        /// ones in this order and read as zero.
+        pub fn u8_from_bits(self, bits: &[Bit]) -> u8 {
+            match self {
+                BitOrder::LsbFirst => u8_from_bits(bits),
+                BitOrder::MsbFirst => {
+                    let mut res = 0;
+                    for (i, bit) in bits.iter().enumerate() {
+                        if *bit == Bit::One {
+                            res |= 0x80 >> i;
+                        }
+                    }
+
+                    res
+                }
+            }
+        }
+    }
+
+    /// Expands every byte of `v` into eight bits.
     pub fn from_bytes(v: &[u8]) -> Vec<Bit> {
         v
             .iter()
@@ -47,14 +294,15 @@ mod utils {
             .collect()
     }
 
+    /// Packs `v` into bytes, zero-padding a trailing partial byte.
     pub fn to_bytes(v: &[Bit]) -> Vec<u8> {
         v
             .chunks(8)
-            .map(|chunk| u8_from_bits(chunk))
+            .map(u8_from_bits)
             .collect()
     }
 
-
+    /// Returns the eight bits of `num`.
     pub fn bits_from_u8(num: u8) -> Vec<Bit> {
         let mut res = vec![];
         for i in 0 .. 8 {
@@ -68,6 +316,7 @@ mod utils {
         res
     }
 
+    /// Packs up to eight bits into a byte.
     pub fn u8_from_bits(bits: &[Bit]) -> u8 {
         let mut res = 0;
         for (i, bit) in bits.iter().enumerate() {
@@ -114,6 +363,18 @@ mod utils {
             assert_eq!(u8_from_bits(&[Zero, One, One]), 6);
         }
 
+        #[test]
+        fn test_bit_order() {
+            use Bit::*;
+            assert_eq!(BitOrder::LsbFirst.bits_from_u8(6), bits_from_u8(6));
+            assert_eq!(BitOrder::MsbFirst.bits_from_u8(6), vec![Zero, Zero, Zero, Zero, Zero, One, One, Zero]);
+            assert_eq!(BitOrder::MsbFirst.u8_from_bits(&[Zero, Zero, Zero, Zero, Zero, One, One, Zero]), 6);
+            assert_eq!(BitOrder::MsbFirst.u8_from_bits(&[One, One]), 0xc0);
+            assert_eq!(BitOrder::LsbFirst.u8_from_bits(&[One, One]), 3);
+            assert_eq!(BitOrder::MsbFirst.to_bytes(&BitOrder::MsbFirst.from_bytes(&[1, 2, 128])), vec![1, 2, 128]);
+            assert_eq!(BitOrder::LsbFirst.from_bytes(&[1, 2, 3]), from_bytes(&[1, 2, 3]));
+        }
+
         #[test]
         fn test_bits_from_u8() {
             use Bit::*;
@@ -126,17 +387,66 @@ mod utils {
 
 }
 
-mod parser {
+/// Turning Boolfuck source into instructions.
+pub mod parser {
     use super::*;
 
-    pub fn parse(code: &str) -> Vec<Instruction> {
-        code
-            .chars()
-            .filter_map(|ch| parse_instruction(ch))
-            .collect()
+    /// Parses `code`, ignoring every character that is not a command.
+    pub fn parse(code: &str) -> Result<Vec<Instruction>, ParseError> {
+        Ok(parse_with_positions(code)?.into_iter().map(|(instruction, _)| instruction).collect())
+    }
+
+    /// Parses `code` like [`parse`], keeping where each instruction is in
+    /// the source.
+    pub fn parse_with_positions(code: &str) -> Result<Vec<(Instruction, Position)>, ParseError> {
+        let instructions: Vec<_> = positions(code)
+            .filter_map(|(ch, position)| parse_instruction(ch).map(|instruction| (instruction, position)))
+            .collect();
+
+        check_brackets(instructions.iter().copied())?;
+        Ok(instructions)
+    }
+
+    pub(crate) fn check_brackets(
+        instructions: impl Iterator<Item = (Instruction, Position)>
+    ) -> Result<(), ParseError> {
+        let mut open = vec![];
+
+        for (instruction, position) in instructions {
+            match instruction {
+                Instruction::SkipRight => open.push(position),
+                Instruction::SkipLeft => {
+                    open.pop().ok_or(ParseError::UnmatchedClose(position))?;
+                },
+                _ => {}
+            }
+        }
+
+        match open.pop() {
+            Some(position) => Err(ParseError::UnmatchedOpen(position)),
+            None => Ok(())
+        }
     }
 
-    fn parse_instruction(ch: char) -> Option<Instruction> {
+    pub(crate) fn positions(code: &str) -> impl Iterator<Item = (char, Position)> + '_ {
+        let mut line = 1;
+        let mut column = 1;
+
+        code.char_indices().map(move |(offset, ch)| {
+            let position = Position { offset, line, column };
+
+            if ch == '\n' {
+                line += 1;
+                column = 1;
+            } else {
+                column += 1;
+            }
+
+            (ch, position)
+        })
+    }
+
+    pub(crate) fn parse_instruction(ch: char) -> Option<Instruction> {
         use Instruction::*;
 
         match ch {
@@ -150,150 +460,533 @@ mod parser {
             _ => None
         }
     }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_parse() {
+            use Instruction::*;
+            assert_eq!(parse("+,;<>[]"), Ok(vec![Flip, Read, Write, MoveLeft, MoveRight, SkipRight, SkipLeft]));
+            assert_eq!(parse("a + b\n[c]"), Ok(vec![Flip, SkipRight, SkipLeft]));
+            assert_eq!(parse(""), Ok(vec![]));
+        }
+
+        #[test]
+        fn test_parse_with_positions() {
+            use Instruction::*;
+            assert_eq!(
+                parse_with_positions("a+\n [;]"),
+                Ok(vec![
+                    (Flip, Position { offset: 1, line: 1, column: 2 }),
+                    (SkipRight, Position { offset: 4, line: 2, column: 2 }),
+                    (Write, Position { offset: 5, line: 2, column: 3 }),
+                    (SkipLeft, Position { offset: 6, line: 2, column: 4 })
+                ])
+            );
+        }
+
+        #[test]
+        fn test_to_char() {
+            let code: String = parse("+,;<>[]").unwrap().iter().map(|instruction| instruction.to_char()).collect();
+            assert_eq!(code, "+,;<>[]");
+            assert_eq!(Instruction::Write.to_string(), ";");
+        }
+
+        #[test]
+        fn test_unmatched_open() {
+            assert_eq!(
+                parse("+[[]"),
+                Err(ParseError::UnmatchedOpen(Position { offset: 1, line: 1, column: 2 }))
+            );
+            assert_eq!(
+                parse("+\n  [\n"),
+                Err(ParseError::UnmatchedOpen(Position { offset: 4, line: 2, column: 3 }))
+            );
+        }
+
+        #[test]
+        fn test_unmatched_close() {
+            assert_eq!(
+                parse("[]]"),
+                Err(ParseError::UnmatchedClose(Position { offset: 2, line: 1, column: 3 }))
+            );
+            assert_eq!(
+                parse("\u{e9}\n\u{e9}]["),
+                Err(ParseError::UnmatchedClose(Position { offset: 5, line: 2, column: 2 }))
+            );
+        }
+    }
 }
 
-mod interpreter {
-    use std::collections::HashMap;
+/// Executing programs.
+pub mod interpreter {
+    use std::io::{Read, Write};
+    use std::sync::Arc;
+    use input::Input;
+    use output::Output;
     use super::*;
 
+    /// What `,` does once the input is exhausted.
+    #[derive (PartialEq, Eq, Copy, Clone, Debug, Default)]
+    pub enum EofPolicy {
+        /// Store a zero bit.
+        #[default]
+        Zero,
+        /// Store a one bit.
+        One,
+        /// Leave the cell as it is.
+        Unchanged,
+        /// Fail with [`RuntimeError::EndOfInput`].
+        Error
+    }
+
+    impl EofPolicy {
+        /// The bit `,` stores in a cell holding `cell` once the input is
+        /// exhausted.
+        pub fn bit(self, cell: Bit) -> Result<Bit, RuntimeError> {
+            match self {
+                EofPolicy::Zero => Ok(Bit::Zero),
+                EofPolicy::One => Ok(Bit::One),
+                EofPolicy::Unchanged => Ok(cell),
+                EofPolicy::Error => Err(RuntimeError::EndOfInput)
+            }
+        }
+    }
+
+    /// Settings that change how an [`Interpreter`] behaves.
+    #[derive (PartialEq, Eq, Copy, Clone, Debug, Default)]
+    pub struct Options {
+        /// What to do with a trailing partial output byte at halt.
+        pub partial_byte: PartialByte,
+        /// What `,` does once the input is exhausted.
+        pub eof: EofPolicy,
+        /// The order in which input and output bytes are split into bits.
+        pub bit_order: BitOrder,
+        /// The most cells the tape may span, if limited.
+        pub tape_limit: Option<usize>,
+        /// Pause with [`Status::NeedsInput`] when `,` finds no input,
+        /// instead of applying the EOF policy, until
+        /// [`Interpreter::close_input`] is called. Also pause when the
+        /// reader would block, which otherwise fails with
+        /// [`RuntimeError::Io`]. Compiled programs cannot pause, so they
+        /// ignore this and apply the EOF policy.
+        pub suspend_on_input: bool
+    }
+
+    /// Why the interpreter stopped.
+    #[derive (PartialEq, Eq, Copy, Clone, Debug)]
+    pub enum Status {
+        /// The program ran off its end.
+        Halted,
+        /// The step budget ran out; calling `run_for` again resumes.
+        OutOfFuel,
+        /// A `,` found no input. [`Interpreter::feed`] more and run again
+        /// to resume at that `,`.
+        NeedsInput
+    }
+
+    /// The state of one run of a [`Program`]: a tape that grows in both
+    /// directions as the pointer moves, plus input and output.
+    ///
+    /// The program itself is shared, so many interpreters can run the same
+    /// program without reparsing it.
     pub struct Interpreter {
-        tape: Vec<Bit>,
-        output: Vec<Bit>,
-        input: Vec<Bit>,
-        program: Vec<Instruction>,
+        tape: Tape,
+        output: Output,
+        input: Input,
+        program: Arc<Program>,
         program_pointer: usize,
-        pointer: usize,
-        matches: HashMap<usize, usize>
+        pointer: isize,
+        options: Options,
+        finished: bool,
+        /// The instruction that broke the tape limit, when the op that
+        /// failed folds several together.
+        failed_instruction: Option<usize>
     }
 
     impl Interpreter {
-        pub fn new(program: Vec<Instruction>, input: Vec<Bit>) -> Interpreter {
+        /// Prepares to run `program` with `input` as the bits read by `,`.
+        pub fn new(program: impl Into<Arc<Program>>, input: Vec<Bit>) -> Interpreter {
+            Interpreter::from_input(program.into(), Input::from_bits(input))
+        }
+
+        /// Prepares to run `program`, decoding the bits read by `,` from
+        /// `reader` one byte at a time as they are needed.
+        pub fn from_reader(program: impl Into<Arc<Program>>, reader: impl Read + Send + 'static) -> Interpreter {
+            Interpreter::from_input(program.into(), Input::from_reader(reader))
+        }
+
+        fn from_input(program: Arc<Program>, input: Input) -> Interpreter {
             Interpreter {
-                tape: vec![Bit::Zero],
+                tape: Tape::new(),
                 pointer: 0,
                 program_pointer: 0,
-                output: vec![],
-                matches: HashMap::new(),
-                input,
+                output: Output::buffer(),
+                options: Options::default(),
+                finished: false,
+                failed_instruction: None,
                 program,
+                input,
             }
         }
 
-        pub fn interpret(&mut self) {
-            use Instruction::*;
-            self.create_matches();
-
-            while self.program_pointer < self.program.len() {
-                match self.program[self.program_pointer] {
-                    Flip => self.flip(),
-                    Read => self.read(),
-                    Write => self.write(),
-                    MoveLeft => self.move_left(),
-                    MoveRight => self.move_right(),
-                    SkipLeft => self.skip_left(),
-                    SkipRight => self.skip_right(),
+        /// Sends every output byte to `writer` as soon as its eighth bit is
+        /// written, instead of collecting it for [`Interpreter::get_output`].
+        pub fn with_output(mut self, writer: impl Write + Send + 'static) -> Interpreter {
+            self.output = Output::to_writer(writer);
+            self
+        }
+
+        pub fn with_options(mut self, options: Options) -> Interpreter {
+            self.options = options;
+            self
+        }
+
+        /// Runs the program until it falls off the end, or until it waits
+        /// for input when [`Options::suspend_on_input`] is set.
+        pub fn interpret(&mut self) -> Result<Status, RuntimeError> {
+            self.failed_instruction = None;
+            while !self.is_halted() {
+                if !self.step()? {
+                    return Ok(Status::NeedsInput);
+                }
+            }
+
+            self.finish()?;
+            Ok(Status::Halted)
+        }
+
+        /// Executes at most `steps` ops of the [`Program`].
+        ///
+        /// An op can stand for many instructions: a folded run of moves, a
+        /// loop idiom such as `[+]` and a translated Brainfuck command each
+        /// count as a single step. To count instructions, run a program
+        /// built with [`Program::unoptimized`], whose ops are one per
+        /// instruction.
+        ///
+        /// The interpreter stays resumable, so a program can be run in
+        /// slices by calling this repeatedly until it reports
+        /// [`Status::Halted`].
+        ///
+        /// If an instruction fails, the program pointer stays on it.
+        pub fn run_for(&mut self, steps: u64) -> Result<Status, RuntimeError> {
+            self.failed_instruction = None;
+            for _ in 0 .. steps {
+                if self.is_halted() {
+                    break;
+                }
+
+                if !self.step()? {
+                    return Ok(Status::NeedsInput);
                 }
             }
+
+            if self.is_halted() {
+                self.finish()?;
+                Ok(Status::Halted)
+            } else {
+                Ok(Status::OutOfFuel)
+            }
         }
 
-        pub fn get_output(&self) -> &Vec<Bit> {
-            &self.output
+        /// Queues `bytes` as input. They come after input fed earlier and
+        /// the rest of any byte already taken from the reader, but before
+        /// the bytes the reader has yet to give, so a program can be fed
+        /// while its reader would block.
+        pub fn feed(&mut self, bytes: &[u8]) {
+            self.input.feed(self.options.bit_order.from_bytes(bytes));
         }
 
-        fn get_tape(&self) -> &Vec<Bit> {
-            &self.tape
+        /// Queues `bits` as input, in the same place as [`Interpreter::feed`].
+        pub fn feed_bits(&mut self, bits: &[Bit]) {
+            self.input.feed(bits.iter().copied());
         }
 
-        fn create_matches(&mut self) {
-            let mut matches = vec![];
-
-            for (i, instr) in self.program.iter().enumerate() {
-                match instr {
-                    &Instruction::SkipRight => {
-                        matches.push(i);
-                    },
-                    &Instruction::SkipLeft => {
-                        let prev_i = matches.pop().unwrap();
-                        self.matches.insert(prev_i, i);
-                        self.matches.insert(i, prev_i);
-                    },
-                    _ => {}
-                }
+        /// Declares that no more input will be fed, so an exhausted input
+        /// follows [`Options::eof`] instead of suspending.
+        pub fn close_input(&mut self) {
+            self.input.close();
+        }
+
+        /// Appends `program` to the program being run, so a halted
+        /// interpreter can carry on with more instructions against the same
+        /// tape. The program is copied first if other interpreters share it.
+        pub fn append(&mut self, program: Program) {
+            Arc::make_mut(&mut self.program).extend(program);
+            self.finished = false;
+        }
+
+        /// Abandons the rest of the program, as if it had run off its end.
+        pub fn halt(&mut self) {
+            self.program_pointer = self.program.ops().len();
+        }
+
+        /// Whether the program has run off its end.
+        pub fn is_halted(&self) -> bool {
+            self.program_pointer >= self.program.ops().len()
+        }
+
+        fn finish(&mut self) -> Result<(), RuntimeError> {
+            if !self.finished {
+                self.output.finish(self.options.partial_byte, self.options.bit_order)?;
+                self.finished = true;
+            }
+
+            Ok(())
+        }
+
+        /// Executes one op. Returns false, without executing it, if it is
+        /// a `,` that has to wait for input.
+        fn step(&mut self) -> Result<bool, RuntimeError> {
+            match self.program.ops()[self.program_pointer] {
+                Op::Flip => self.flip(),
+                Op::Read => return self.read(),
+                Op::Write => self.write()?,
+                Op::Move { by, low, high } => self.move_by(by, low, high)?,
+                Op::SkipLeft(target) => self.skip_left(target),
+                Op::SkipRight(target) => self.skip_right(target),
+                Op::SetZero => self.set_zero(),
+                Op::ScanRight => self.scan_right()?,
+                Op::ScanLeft => self.scan_left()?,
+                Op::IncByte(len) => self.add_to_byte(len, 1),
+                Op::DecByte(len) => self.add_to_byte(len, u8::MAX),
+                Op::ReadByte(len) => self.read_byte(len)?,
+                Op::WriteByte(len) => self.write_byte(len)?,
+                Op::ByteLoopStart(len, target) => self.byte_loop_start(len, target),
+                Op::ByteLoopEnd(len, target) => self.byte_loop_end(len, target)
             }
+
+            Ok(true)
+        }
+
+        /// The complete bytes written by `;` so far. Empty when writing to
+        /// a writer given to [`Interpreter::with_output`].
+        pub fn get_output(&self) -> &[u8] {
+            self.output.bytes()
+        }
+
+        /// The bits of the output byte that is not complete yet.
+        pub fn get_pending_output(&self) -> &[Bit] {
+            self.output.pending()
+        }
+
+        /// The cells visited so far, leftmost first.
+        pub fn get_tape(&self) -> Vec<Bit> {
+            self.tape.bits()
+        }
+
+        pub fn tape(&self) -> &Tape {
+            &self.tape
+        }
+
+        /// The cell under the pointer, relative to where it started.
+        pub fn pointer(&self) -> isize {
+            self.pointer
         }
 
-        fn get_matching_pointer(&self, i: usize) -> usize {
-            *self.matches.get(&i).unwrap()
+        /// Where in the source the next op to run came from, or `None`
+        /// once the program has halted or if it was not parsed from source.
+        /// After a [`RuntimeError`] this is the op that failed, or for a
+        /// broken tape limit the move that broke it.
+        ///
+        /// After [`Interpreter::append`], the position is in the source of
+        /// the program that [`Interpreter::part`] names.
+        pub fn position(&self) -> Option<Position> {
+            let instruction = self.instruction()?;
+            self.program.positions().map(|positions| positions[instruction])
+        }
+
+        /// Which program the op [`Interpreter::position`] describes came
+        /// from: zero for the one the interpreter was made with, and one
+        /// more for each [`Interpreter::append`] after it. `None` once the
+        /// program has halted.
+        pub fn part(&self) -> Option<usize> {
+            self.instruction().map(|instruction| self.program.part(instruction))
+        }
+
+        /// The instruction [`Interpreter::position`] describes.
+        fn instruction(&self) -> Option<usize> {
+            if self.is_halted() {
+                return None;
+            }
+
+            Some(self.failed_instruction.unwrap_or_else(|| self.program.source(self.program_pointer)))
         }
 
         fn flip(&mut self) {
-            self.tape[self.pointer] = self.tape[self.pointer].flip();
+            self.tape.flip(self.pointer);
             self.program_pointer += 1;
         }
 
-        fn read(&mut self) {
-            self.tape[self.pointer] = if self.input.len() == 0 {
-                Bit::Zero
-            } else {
-                self.input.remove(0)
+        fn read(&mut self) -> Result<bool, RuntimeError> {
+            let bit = match self.input.read_bit(self.options.bit_order) {
+                Ok(Some(bit)) => bit,
+                Ok(None) if self.options.suspend_on_input && !self.input.is_closed() => return Ok(false),
+                Ok(None) => self.options.eof.bit(self.tape.get(self.pointer))?,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock && self.options.suspend_on_input => return Ok(false),
+                Err(e) => return Err(e.into())
             };
 
+            self.tape.set(self.pointer, bit);
             self.program_pointer += 1;
+            Ok(true)
         }
 
-        fn write(&mut self) {
-            self.output.push(self.tape[self.pointer]);
+        fn write(&mut self) -> Result<(), RuntimeError> {
+            self.output.write_bit(self.tape.get(self.pointer), self.options.bit_order)?;
             self.program_pointer += 1;
+            Ok(())
         }
 
-        fn move_left(&mut self) {
-            if self.pointer == 0 {
-                self.tape.insert(0, Bit::Zero);
-            } else {
-                self.pointer -= 1;
+        fn move_by(&mut self, by: isize, low: isize, high: isize) -> Result<(), RuntimeError> {
+            let from = self.pointer;
+            if self.tape.visit_within(from + low ..= from + high, self.options.tape_limit).is_err() {
+                return Err(self.replay_moves());
             }
 
+            self.pointer = from + by;
             self.program_pointer += 1;
+            Ok(())
         }
 
-        fn move_right(&mut self) {
-            if self.pointer == self.tape.len() - 1 {
-                self.tape.push(Bit::Zero);
+        /// Runs the moves folded into the op that breaks the tape limit
+        /// one at a time, so the pointer and the visited cells end up
+        /// where the first move to break it leaves them, and returns its
+        /// error. Only flips that cancel out can be folded in between.
+        fn replay_moves(&mut self) -> RuntimeError {
+            let program = Arc::clone(&self.program);
+            let start = program.source(self.program_pointer);
+            for (i, instruction) in program.instructions().iter().enumerate().skip(start) {
+                let by = match instruction {
+                    Instruction::MoveLeft => -1,
+                    Instruction::MoveRight => 1,
+                    _ => continue
+                };
+
+                if let Err(e) = self.move_to(self.pointer + by) {
+                    self.failed_instruction = Some(i);
+                    return e;
+                }
             }
 
-            self.pointer += 1;
+            unreachable!("the moves break the tape limit")
+        }
+
+        fn move_to(&mut self, position: isize) -> Result<(), RuntimeError> {
+            self.tape.visit_within(position ..= position, self.options.tape_limit)?;
+            self.pointer = position;
+            Ok(())
+        }
+
+        fn set_zero(&mut self) {
+            self.tape.set(self.pointer, Bit::Zero);
+            self.program_pointer += 1;
+        }
+
+        fn scan_right(&mut self) -> Result<(), RuntimeError> {
+            self.scan_to(self.tape.scan_right(self.pointer))?;
             self.program_pointer += 1;
+            Ok(())
         }
 
-        fn skip_left(&mut self) {
-            if self.tape[self.pointer] == Bit::One {
-                self.program_pointer = self.get_matching_pointer(self.program_pointer);
+        fn scan_left(&mut self) -> Result<(), RuntimeError> {
+            self.scan_to(self.tape.scan_left(self.pointer))?;
+            self.program_pointer += 1;
+            Ok(())
+        }
+
+        /// Moves to `target`, or if the tape limit forbids it, as far
+        /// towards it as a loop of single moves would get before failing.
+        fn scan_to(&mut self, target: isize) -> Result<(), RuntimeError> {
+            let e = match self.move_to(target) {
+                Err(e @ RuntimeError::TapeLimit(_)) => e,
+                result => return result
+            };
+
+            // The move inside the brackets is the one that breaks it.
+            let start = self.program.source(self.program_pointer);
+            self.failed_instruction = self.program.instructions()[start ..].iter()
+                .position(|instruction| matches!(instruction, Instruction::MoveLeft | Instruction::MoveRight))
+                .map(|i| start + i);
+            let limit = self.options.tape_limit.expect("only a limited tape can break its limit") as isize;
+            let visited = self.tape.visited();
+            let furthest = if target > self.pointer { visited.start() + limit - 1 } else { visited.end() - limit + 1 };
+            self.move_to(furthest)?;
+            Err(e)
+        }
+
+        fn skip_left(&mut self, target: usize) {
+            if self.tape.get(self.pointer) == Bit::One {
+                self.program_pointer = target;
             } else {
                 self.program_pointer += 1;
             }
         }
 
-        fn skip_right(&mut self) {
-            if self.tape[self.pointer] == Bit::Zero {
-                self.program_pointer = self.get_matching_pointer(self.program_pointer);
+        fn skip_right(&mut self, target: usize) {
+            if self.tape.get(self.pointer) == Bit::Zero {
+                self.program_pointer = target;
             } else {
                 self.program_pointer += 1;
             }
         }
+
+        /// Moves past a byte op whose translation is `len` ops long, and
+        /// past the translation too if the fast path ran it.
+        fn skip_byte_op(&mut self, len: usize, fast: bool) {
+            self.program_pointer += if fast { 1 + len } else { 1 };
+        }
+
+        fn add_to_byte(&mut self, len: usize, delta: u8) {
+            let fast = self.tape.add_to_byte(self.pointer, delta, self.options.tape_limit);
+            self.skip_byte_op(len, fast);
+        }
+
+        fn read_byte(&mut self, len: usize) -> Result<(), RuntimeError> {
+            let fast = self.tape.read_byte(self.pointer, &mut self.input, self.options.bit_order, self.options.tape_limit)?;
+            self.skip_byte_op(len, fast);
+            Ok(())
+        }
+
+        fn write_byte(&mut self, len: usize) -> Result<(), RuntimeError> {
+            let fast = self.tape.write_byte(self.pointer, &mut self.output, self.options.bit_order, self.options.tape_limit)?;
+            self.skip_byte_op(len, fast);
+            Ok(())
+        }
+
+        fn byte_loop_start(&mut self, len: usize, target: usize) {
+            match self.tape.loop_byte(self.pointer, self.options.tape_limit) {
+                Some(0) => self.program_pointer = ir::past_translation(self.program.ops(), target),
+                fast => self.skip_byte_op(len, fast.is_some())
+            }
+        }
+
+        fn byte_loop_end(&mut self, len: usize, target: usize) {
+            match self.tape.loop_byte(self.pointer, self.options.tape_limit) {
+                Some(byte) if byte != 0 => self.program_pointer = ir::past_translation(self.program.ops(), target),
+                fast => self.skip_byte_op(len, fast.is_some())
+            }
+        }
     }
 
     #[cfg(test)]
     mod tests {
         use super::*;
 
+        fn program(instructions: Vec<Instruction>) -> Program {
+            Program::new(instructions).unwrap()
+        }
+
         #[test]
         fn test_flip() {
             use Instruction::*;
             use Bit::*;
 
-            let mut interpreter = Interpreter::new(vec![Flip, Flip, Flip], vec![]);
-            interpreter.interpret();
-            assert_eq!(interpreter.get_tape(), &vec![One]);
+            let mut interpreter = Interpreter::new(program(vec![Flip, Flip, Flip]), vec![]);
+            interpreter.interpret().unwrap();
+            assert_eq!(interpreter.get_tape(), vec![One]);
         }
 
         #[test]
@@ -301,13 +994,15 @@ mod interpreter {
             use Instruction::*;
             use Bit::*;
 
-            let mut interpreter = Interpreter::new(vec![MoveLeft], vec![]);
-            interpreter.interpret();
-            assert_eq!(interpreter.get_tape(), &vec![Zero, Zero]);
+            let mut interpreter = Interpreter::new(program(vec![MoveLeft]), vec![]);
+            interpreter.interpret().unwrap();
+            assert_eq!(interpreter.get_tape(), vec![Zero, Zero]);
 
-            let mut interpreter = Interpreter::new(vec![MoveLeft, MoveLeft, MoveLeft], vec![]);
-            interpreter.interpret();
-            assert_eq!(interpreter.get_tape(), &vec![Zero, Zero, Zero, Zero]);
+            let mut interpreter = Interpreter::new(program(vec![MoveLeft, MoveLeft, MoveLeft]), vec![]);
+            interpreter.interpret().unwrap();
+            assert_eq!(interpreter.get_tape(), vec![Zero, Zero, Zero, Zero]);
+            assert_eq!(interpreter.pointer(), -3);
+            assert_eq!(interpreter.tape().visited(), -3 ..= 0);
         }
 
         #[test]
@@ -315,13 +1010,18 @@ mod interpreter {
             use Instruction::*;
             use Bit::*;
 
-            let mut interpreter = Interpreter::new(vec![MoveRight], vec![]);
-            interpreter.interpret();
-            assert_eq!(interpreter.get_tape(), &vec![Zero, Zero]);
+            let mut interpreter = Interpreter::new(program(vec![MoveRight]), vec![]);
+            interpreter.interpret().unwrap();
+            assert_eq!(interpreter.get_tape(), vec![Zero, Zero]);
 
-            let mut interpreter = Interpreter::new(vec![MoveRight, MoveRight, MoveRight], vec![]);
-            interpreter.interpret();
-            assert_eq!(interpreter.get_tape(), &vec![Zero, Zero, Zero, Zero]);
+            let mut interpreter = Interpreter::new(program(vec![MoveRight, MoveRight, MoveRight]), vec![]);
+            interpreter.interpret().unwrap();
+            assert_eq!(interpreter.get_tape(), vec![Zero, Zero, Zero, Zero]);
+
+            let mut interpreter = Interpreter::new(program(vec![MoveRight, MoveRight, MoveRight, MoveLeft]), vec![]);
+            interpreter.interpret().unwrap();
+            assert_eq!(interpreter.pointer(), 2);
+            assert_eq!(interpreter.tape().visited(), 0 ..= 3);
         }
 
         #[test]
@@ -329,12 +1029,12 @@ mod interpreter {
             use Instruction::*;
             use Bit::*;
 
-            let mut interpreter = Interpreter::new(vec![SkipRight, Flip, SkipLeft], vec![]);
-            interpreter.interpret();
-            assert_eq!(interpreter.get_tape(), &vec![Zero]);
+            let mut interpreter = Interpreter::new(program(vec![SkipRight, Flip, SkipLeft]), vec![]);
+            interpreter.interpret().unwrap();
+            assert_eq!(interpreter.get_tape(), vec![Zero]);
 
             let mut interpreter = Interpreter::new(
-                vec![
+                program(vec![
                     Flip,
                     MoveRight,
                     MoveRight,
@@ -347,11 +1047,11 @@ mod interpreter {
                     MoveRight,
                     Flip,
                     SkipLeft
-                ],
+                ]),
                 vec![]
             );
-            interpreter.interpret();
-            assert_eq!(interpreter.get_tape(), &vec![One, One, One, Zero]);
+            interpreter.interpret().unwrap();
+            assert_eq!(interpreter.get_tape(), vec![One, One, One, Zero]);
         }
 
         #[test]
@@ -359,9 +1059,9 @@ mod interpreter {
             use Instruction::*;
             use Bit::*;
 
-            let mut interpreter = Interpreter::new(vec![Read, MoveRight, Read, MoveRight, Read], vec![One, One, One]);
-            interpreter.interpret();
-            assert_eq!(interpreter.get_tape(), &vec![One, One, One]);
+            let mut interpreter = Interpreter::new(program(vec![Read, MoveRight, Read, MoveRight, Read]), vec![One, One, One]);
+            interpreter.interpret().unwrap();
+            assert_eq!(interpreter.get_tape(), vec![One, One, One]);
         }
 
         #[test]
@@ -369,9 +1069,471 @@ mod interpreter {
             use Instruction::*;
             use Bit::*;
 
-            let mut interpreter = Interpreter::new(vec![Read, Write, MoveRight, Read, Write, MoveRight, Read, Write], vec![One, One, One]);
-            interpreter.interpret();
-            assert_eq!(interpreter.get_output(), &vec![One, One, One]);
+            let mut interpreter = Interpreter::new(program(vec![Read, Write, MoveRight, Read, Write, MoveRight, Read, Write]), vec![One, One, One]);
+            interpreter.interpret().unwrap();
+            assert_eq!(interpreter.get_output(), &[7]);
+        }
+
+        #[test]
+        fn test_run_for() {
+            use Instruction::*;
+            use Bit::*;
+
+            let mut interpreter = Interpreter::new(program(vec![Flip, SkipRight, SkipLeft]), vec![]);
+            assert_eq!(interpreter.run_for(1000).unwrap(), Status::OutOfFuel);
+            assert_eq!(interpreter.run_for(1000).unwrap(), Status::OutOfFuel);
+            assert!(!interpreter.is_halted());
+
+            let mut interpreter = Interpreter::new(program(vec![Flip, Write, Flip, Write]), vec![]);
+            assert_eq!(interpreter.run_for(0).unwrap(), Status::OutOfFuel);
+            assert_eq!(interpreter.run_for(3).unwrap(), Status::OutOfFuel);
+            assert_eq!(interpreter.get_pending_output(), &[One]);
+            assert_eq!(interpreter.run_for(1).unwrap(), Status::Halted);
+            assert_eq!(interpreter.get_output(), &[1]);
+            assert!(interpreter.get_pending_output().is_empty());
+            assert_eq!(interpreter.run_for(1).unwrap(), Status::Halted);
+
+            let mut interpreter = Interpreter::new(program(vec![]), vec![]);
+            assert_eq!(interpreter.run_for(0).unwrap(), Status::Halted);
+
+            // A step is an op, which may stand for many instructions.
+            let moves = vec![MoveRight, MoveRight, MoveRight, Write];
+            let mut interpreter = Interpreter::new(program(moves.clone()), vec![]);
+            assert_eq!(interpreter.run_for(2).unwrap(), Status::Halted);
+            let mut interpreter = Interpreter::new(Program::unoptimized(moves).unwrap(), vec![]);
+            assert_eq!(interpreter.run_for(2).unwrap(), Status::OutOfFuel);
+            assert_eq!(interpreter.pointer(), 2);
+        }
+
+        #[test]
+        fn test_run_for_resumes() {
+            let code = ">,>,>,>,>,>,>,>,<<<<<<<<>[>]+<[+<]>>>>>>>>>[+]<<<<<<<<<>;>;>;>;>;>;>;>;";
+            let input = utils::from_bytes(&[41]);
+
+            let mut whole = Interpreter::new(Program::parse(code).unwrap(), input.clone());
+            whole.interpret().unwrap();
+
+            let mut sliced = Interpreter::new(Program::parse(code).unwrap(), input);
+            while sliced.run_for(3).unwrap() == Status::OutOfFuel {}
+
+            assert_eq!(whole.get_output(), &[42]);
+            assert_eq!(sliced.get_output(), whole.get_output());
+            assert_eq!(sliced.get_tape(), whole.get_tape());
+        }
+
+        #[test]
+        fn test_from_reader() {
+            let code = ">,>,>,>,>,>,>,>,<<<<<<<<>[>]+<[+<]>>>>>>>>>[+]<<<<<<<<<>;>;>;>;>;>;>;>;";
+            let mut interpreter = Interpreter::from_reader(Program::parse(code).unwrap(), &b"\x07"[..]);
+            interpreter.interpret().unwrap();
+            assert_eq!(interpreter.get_output(), &[8]);
+        }
+
+        #[test]
+        fn test_with_output() {
+            use std::sync::Mutex;
+
+            #[derive (Clone, Default)]
+            struct Shared(Arc<Mutex<Vec<u8>>>);
+
+            impl Write for Shared {
+                fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                    self.0.lock().unwrap().extend_from_slice(buf);
+                    Ok(buf.len())
+                }
+
+                fn flush(&mut self) -> std::io::Result<()> {
+                    Ok(())
+                }
+            }
+
+            let shared = Shared::default();
+            let code = "+;;;;;;;;[]";
+            let mut interpreter = Interpreter::new(Program::parse(code).unwrap(), vec![]).with_output(shared.clone());
+            assert_eq!(interpreter.run_for(100).unwrap(), Status::OutOfFuel);
+            assert_eq!(*shared.0.lock().unwrap(), vec![255]);
+            assert!(interpreter.get_output().is_empty());
+        }
+
+        #[test]
+        fn test_retry_write() {
+            use std::sync::Mutex;
+
+            /// A writer whose first write would block.
+            #[derive (Clone, Default)]
+            struct Flaky(Arc<Mutex<Vec<u8>>>, Arc<Mutex<bool>>);
+
+            impl Write for Flaky {
+                fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                    let mut failed = self.1.lock().unwrap();
+                    if !*failed {
+                        *failed = true;
+                        return Err(std::io::ErrorKind::WouldBlock.into());
+                    }
+                    self.0.lock().unwrap().extend_from_slice(buf);
+                    Ok(buf.len())
+                }
+
+                fn flush(&mut self) -> std::io::Result<()> {
+                    Ok(())
+                }
+            }
+
+            // A failed `;` stays the next op, and running it again writes
+            // the byte once.
+            for (code, expected) in [("+;;;;;;;;;".to_string(), [255, 1]), (brainfuck::translate("+.+.").unwrap(), [1, 2])] {
+                let flaky = Flaky::default();
+                let mut interpreter = Interpreter::new(Program::parse(&code).unwrap(), vec![]).with_output(flaky.clone());
+                assert!(matches!(interpreter.interpret(), Err(RuntimeError::Io(_))));
+                interpreter.interpret().unwrap();
+                assert_eq!(*flaky.0.lock().unwrap(), expected, "{}", code);
+            }
+        }
+
+        #[test]
+        fn test_partial_byte() {
+            use Instruction::*;
+
+            let options = Options { partial_byte: PartialByte::Drop, ..Options::default() };
+            let mut interpreter = Interpreter::new(program(vec![Flip, Write]), vec![]).with_options(options);
+            interpreter.interpret().unwrap();
+            assert!(interpreter.get_output().is_empty());
+
+            let options = Options { partial_byte: PartialByte::Error, ..Options::default() };
+            let mut interpreter = Interpreter::new(program(vec![Flip, Write]), vec![]).with_options(options);
+            assert!(matches!(interpreter.run_for(10), Err(RuntimeError::PartialByte(1))));
+        }
+
+        #[test]
+        fn test_read_error() {
+            struct Failing;
+
+            impl Read for Failing {
+                fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+                    Err(std::io::Error::other("broken pipe"))
+                }
+            }
+
+            let mut interpreter = Interpreter::from_reader(program(vec![Instruction::Read]), Failing);
+            assert!(matches!(interpreter.run_for(10), Err(RuntimeError::Io(_))));
+            assert!(!interpreter.is_halted());
+        }
+
+        #[test]
+        fn test_would_block() {
+            struct Blocking;
+
+            impl Read for Blocking {
+                fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+                    Err(std::io::ErrorKind::WouldBlock.into())
+                }
+            }
+
+            // A reader that is not ready has not reached end of input.
+            let mut interpreter = Interpreter::from_reader(program(vec![Instruction::Read]), Blocking);
+            assert!(matches!(interpreter.run_for(10), Err(RuntimeError::Io(ref e)) if e.kind() == std::io::ErrorKind::WouldBlock));
+            assert!(!interpreter.is_halted());
+
+            let options = Options { suspend_on_input: true, ..Options::default() };
+            let mut interpreter = Interpreter::from_reader(Program::parse(",;").unwrap(), Blocking).with_options(options);
+            assert_eq!(interpreter.run_for(10).unwrap(), Status::NeedsInput);
+            interpreter.feed_bits(&[Bit::One]);
+            interpreter.close_input();
+            assert_eq!(interpreter.interpret().unwrap(), Status::Halted);
+            assert_eq!(interpreter.get_output(), [1]);
+        }
+
+        #[test]
+        fn test_feed_order() {
+            let code = ",;".repeat(16);
+            let mut interpreter = Interpreter::from_reader(Program::parse(&code).unwrap(), std::io::Cursor::new(b"A".to_vec()));
+            interpreter.feed(b"B");
+            interpreter.interpret().unwrap();
+            assert_eq!(interpreter.get_output(), b"BA");
+        }
+
+        #[test]
+        fn test_suspend_on_input() {
+            let code = "+[>,>,>,>,>,>,>,>,<<<<<<<;>;>;>;>;>;>;>;<<<<<<<<]";
+            let options = Options { suspend_on_input: true, ..Options::default() };
+            let mut interpreter = Interpreter::new(Program::parse(code).unwrap(), vec![]).with_options(options);
+
+            assert_eq!(interpreter.run_for(1000).unwrap(), Status::NeedsInput);
+            assert_eq!(interpreter.run_for(1000).unwrap(), Status::NeedsInput);
+            assert!(interpreter.get_output().is_empty());
+
+            interpreter.feed(b"hi");
+            assert_eq!(interpreter.interpret().unwrap(), Status::NeedsInput);
+            assert_eq!(interpreter.get_output(), b"hi");
+
+            interpreter.feed_bits(&utils::bits_from_u8(b'!')[.. 4]);
+            assert_eq!(interpreter.run_for(1000).unwrap(), Status::NeedsInput);
+            interpreter.feed_bits(&utils::bits_from_u8(b'!')[4 ..]);
+            assert_eq!(interpreter.run_for(1000).unwrap(), Status::NeedsInput);
+            assert_eq!(interpreter.get_output(), b"hi!");
+
+            interpreter.close_input();
+            assert_eq!(interpreter.run_for(1000).unwrap(), Status::OutOfFuel);
+            assert_eq!(&interpreter.get_output()[.. 4], b"hi!\0");
+        }
+
+        #[test]
+        fn test_exhausted_input_reads_zero() {
+            use Instruction::*;
+
+            let mut interpreter = Interpreter::new(program(vec![Flip, Read, Write]), vec![]);
+            assert_eq!(interpreter.interpret().unwrap(), Status::Halted);
+            assert_eq!(interpreter.get_output(), &[0]);
+        }
+
+        #[test]
+        fn test_eof_policy() {
+            use Instruction::*;
+
+            let run = |eof| {
+                let options = Options { eof, ..Options::default() };
+                let mut interpreter = Interpreter::new(program(vec![Read, Write, Read, Write, Flip, Read, Write]), vec![Bit::One])
+                    .with_options(options);
+                interpreter.interpret().map(|_| interpreter.get_output().to_vec())
+            };
+
+            assert_eq!(run(EofPolicy::Zero).unwrap(), vec![0b001]);
+            assert_eq!(run(EofPolicy::One).unwrap(), vec![0b111]);
+            assert_eq!(run(EofPolicy::Unchanged).unwrap(), vec![0b011]);
+            assert!(matches!(run(EofPolicy::Error), Err(RuntimeError::EndOfInput)));
+        }
+
+        #[test]
+        fn test_eof_policy_after_close() {
+            use Instruction::*;
+
+            let options = Options { eof: EofPolicy::Error, suspend_on_input: true, ..Options::default() };
+            let mut interpreter = Interpreter::new(program(vec![Read]), vec![]).with_options(options);
+            assert_eq!(interpreter.run_for(10).unwrap(), Status::NeedsInput);
+            interpreter.close_input();
+            assert!(matches!(interpreter.run_for(10), Err(RuntimeError::EndOfInput)));
+        }
+
+        #[test]
+        fn test_bit_order() {
+            let code = ">,>,>,>,>,>,>,>,<<<<<<<<>;>;>;>;>;>;>;>;<<<<<<<<>;";
+            let options = Options { bit_order: BitOrder::MsbFirst, ..Options::default() };
+
+            let mut interpreter = Interpreter::from_reader(Program::parse(code).unwrap(), &[0x81, 0x80][..])
+                .with_options(options);
+            interpreter.interpret().unwrap();
+            assert_eq!(interpreter.get_output(), &[0x81, 0x80]);
+
+            let mut interpreter = Interpreter::new(Program::parse(code).unwrap(), vec![]).with_options(options);
+            interpreter.feed(&[0x40]);
+            interpreter.interpret().unwrap();
+            assert_eq!(interpreter.get_output(), &[0x40, 0x00]);
+        }
+
+        #[test]
+        fn test_position() {
+            let program = Program::parse("+\n>>\n ,").unwrap();
+            let options = Options { eof: EofPolicy::Error, ..Options::default() };
+            let mut interpreter = Interpreter::new(program, vec![]).with_options(options);
+            assert_eq!(interpreter.position(), Some(Position { offset: 0, line: 1, column: 1 }));
+            assert!(matches!(interpreter.interpret(), Err(RuntimeError::EndOfInput)));
+            assert_eq!(interpreter.position(), Some(Position { offset: 6, line: 3, column: 2 }));
+
+            assert_eq!(interpreter.part(), Some(0));
+
+            let mut interpreter = Interpreter::new(Program::parse("+").unwrap(), vec![]);
+            interpreter.interpret().unwrap();
+            assert_eq!(interpreter.position(), None);
+            assert_eq!(interpreter.part(), None);
+
+            // Appended code reports where it is in its own source.
+            interpreter.append(Program::parse(";\n,").unwrap());
+            interpreter.close_input();
+            let options = Options { eof: EofPolicy::Error, ..Options::default() };
+            let mut interpreter = interpreter.with_options(options);
+            assert!(matches!(interpreter.interpret(), Err(RuntimeError::EndOfInput)));
+            assert_eq!(interpreter.position(), Some(Position { offset: 2, line: 2, column: 1 }));
+            assert_eq!(interpreter.part(), Some(1));
+
+            let mut interpreter = Interpreter::new(Program::new(vec![Instruction::Read]).unwrap(), vec![]).with_options(options);
+            assert!(interpreter.interpret().is_err());
+            assert_eq!(interpreter.position(), None);
+        }
+
+        #[test]
+        fn test_tape_limit_position() {
+            let limited = |tape_limit| Options { tape_limit: Some(tape_limit), ..Options::default() };
+
+            let mut interpreter = Interpreter::new(Program::parse(">>>").unwrap(), vec![]).with_options(limited(2));
+            assert!(matches!(interpreter.interpret(), Err(RuntimeError::TapeLimit(2))));
+            assert_eq!(interpreter.position(), Some(Position { offset: 1, line: 1, column: 2 }));
+
+            let mut interpreter = Interpreter::new(Program::parse("+>+>+<<[>]").unwrap(), vec![]).with_options(limited(3));
+            assert!(matches!(interpreter.interpret(), Err(RuntimeError::TapeLimit(3))));
+            assert_eq!(interpreter.position(), Some(Position { offset: 8, line: 1, column: 9 }));
+
+            let mut interpreter = Interpreter::new(Program::parse("+>+>+<<[[>]]").unwrap(), vec![]).with_options(limited(3));
+            assert!(matches!(interpreter.interpret(), Err(RuntimeError::TapeLimit(3))));
+            assert_eq!(interpreter.position(), Some(Position { offset: 9, line: 1, column: 10 }));
+
+            let mut interpreter = Interpreter::new(Program::parse("+<+<+>>[[<]]").unwrap(), vec![]).with_options(limited(3));
+            assert!(matches!(interpreter.interpret(), Err(RuntimeError::TapeLimit(3))));
+            assert_eq!(interpreter.position(), Some(Position { offset: 9, line: 1, column: 10 }));
+        }
+
+        #[test]
+        fn test_tape_limit() {
+            use Instruction::*;
+
+            let options = Options { tape_limit: Some(3), ..Options::default() };
+            let mut interpreter = Interpreter::new(program(vec![MoveRight, MoveRight, MoveLeft, MoveLeft, MoveLeft]), vec![])
+                .with_options(options);
+            assert!(matches!(interpreter.interpret(), Err(RuntimeError::TapeLimit(3))));
+            assert_eq!(interpreter.pointer(), 0);
+            assert_eq!(interpreter.tape().len(), 3);
+
+            let mut interpreter = Interpreter::new(program(vec![MoveRight, MoveRight, MoveLeft, MoveLeft]), vec![])
+                .with_options(options);
+            assert_eq!(interpreter.interpret().unwrap(), Status::Halted);
+
+            // Moves that cancel out still visit cells.
+            let options = Options { tape_limit: Some(2), ..Options::default() };
+            let mut interpreter = Interpreter::new(Program::parse(">><<").unwrap(), vec![]).with_options(options);
+            assert!(matches!(interpreter.interpret(), Err(RuntimeError::TapeLimit(2))));
+            assert_eq!(interpreter.pointer(), 1);
+        }
+
+        fn brainfuck(code: &str) -> Program {
+            Program::parse(&brainfuck::translate(code).unwrap()).unwrap()
+        }
+
+        #[test]
+        fn test_byte_ops() {
+            // Each Brainfuck command runs as a single op.
+            let mut interpreter = Interpreter::new(brainfuck("++++++++[>++++++++<-]>+.,-."), vec![]);
+            interpreter.feed(b"b");
+            assert_eq!(interpreter.run_for(250).unwrap(), Status::Halted);
+            assert_eq!(interpreter.get_output(), b"Aa");
+
+            // A disturbed layout falls back to the translation.
+            let mut interpreter = Interpreter::new(Program::parse(&format!(">>>>>>>>>+<<<<<<<<<{}", brainfuck::translation('+'))).unwrap(), vec![]);
+            interpreter.interpret().unwrap();
+            assert_eq!(interpreter.tape().byte(1), 1);
+            assert_eq!(interpreter.tape().get(9), Bit::Zero);
+        }
+
+        #[test]
+        fn test_byte_ops_suspend_on_input() {
+            let options = Options { suspend_on_input: true, ..Options::default() };
+            let mut interpreter = Interpreter::new(brainfuck(",."), vec![]).with_options(options);
+            interpreter.feed_bits(&utils::bits_from_u8(b'!')[.. 3]);
+            assert_eq!(interpreter.interpret().unwrap(), Status::NeedsInput);
+            interpreter.feed_bits(&utils::bits_from_u8(b'!')[3 ..]);
+            assert_eq!(interpreter.interpret().unwrap(), Status::Halted);
+            assert_eq!(interpreter.get_output(), b"!");
+        }
+
+        #[test]
+        fn test_byte_ops_tape_limit() {
+            let options = Options { tape_limit: Some(9), ..Options::default() };
+            let mut interpreter = Interpreter::new(brainfuck("+"), vec![]).with_options(options);
+            assert!(matches!(interpreter.interpret(), Err(RuntimeError::TapeLimit(9))));
+            assert_eq!(interpreter.tape().byte(1), 1);
+
+            let options = Options { tape_limit: Some(10), ..Options::default() };
+            let mut interpreter = Interpreter::new(brainfuck("+"), vec![]).with_options(options);
+            assert_eq!(interpreter.interpret().unwrap(), Status::Halted);
+        }
+
+        #[test]
+        fn test_append() {
+            use Bit::*;
+
+            let options = Options { partial_byte: PartialByte::Keep, ..Options::default() };
+            let mut interpreter = Interpreter::new(Program::parse("+>+").unwrap(), vec![]).with_options(options);
+            assert_eq!(interpreter.interpret().unwrap(), Status::Halted);
+
+            interpreter.append(Program::parse("<[;>]").unwrap());
+            assert!(!interpreter.is_halted());
+            assert_eq!(interpreter.interpret().unwrap(), Status::Halted);
+            assert_eq!(interpreter.get_tape(), vec![One, One, Zero]);
+            assert_eq!(interpreter.get_pending_output(), &[One, One]);
+
+            interpreter.append(Program::parse(";;;;;;").unwrap());
+            interpreter.interpret().unwrap();
+            assert_eq!(interpreter.get_output(), &[3]);
+        }
+
+        #[test]
+        fn test_append_to_shared_program() {
+            use Instruction::*;
+
+            let shared = Arc::new(program(vec![Flip]));
+            let mut interpreter = Interpreter::new(Arc::clone(&shared), vec![]);
+            interpreter.append(program(vec![Write]));
+            interpreter.interpret().unwrap();
+            assert_eq!(interpreter.get_output(), &[1]);
+            assert_eq!(shared.len(), 1);
+        }
+
+        #[test]
+        fn test_halt() {
+            let mut interpreter = Interpreter::new(Program::parse("+[]").unwrap(), vec![]);
+            assert_eq!(interpreter.run_for(10).unwrap(), Status::OutOfFuel);
+            interpreter.halt();
+            assert_eq!(interpreter.run_for(10).unwrap(), Status::Halted);
+        }
+
+        #[test]
+        fn test_shared_program() {
+            use std::thread;
+            use Instruction::*;
+            use Bit::*;
+
+            let program = Arc::new(program(vec![Read, Write, Write]));
+            let handles: Vec<_> = vec![Zero, One]
+                .into_iter()
+                .map(|bit| {
+                    let program = Arc::clone(&program);
+                    thread::spawn(move || {
+                        let mut interpreter = Interpreter::new(program, vec![bit]);
+                        interpreter.interpret().unwrap();
+                        interpreter.get_output().to_vec()
+                    })
+                })
+                .collect();
+
+            let outputs: Vec<_> = handles.into_iter().map(|handle| handle.join().unwrap()).collect();
+            assert_eq!(outputs, vec![vec![0], vec![3]]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_boolfuck() {
+        assert_eq!(boolfuck(testing::HELLO, vec![]).unwrap(), b"Hello, world!\n");
+    }
+
+    #[test]
+    fn test_boolfuck_with() {
+        let code = ";;;;;;;+;";
+        assert_eq!(boolfuck(code, vec![]).unwrap(), vec![0x80]);
+
+        let options = Options { bit_order: BitOrder::MsbFirst, ..Options::default() };
+        assert_eq!(boolfuck_with(code, vec![], options).unwrap(), vec![0x01]);
+    }
+
+    #[test]
+    fn test_boolfuck_parse_error() {
+        match boolfuck("+]", vec![]) {
+            Err(Error::Parse(e)) => {
+                assert_eq!(e, ParseError::UnmatchedClose(Position { offset: 1, line: 1, column: 2 }));
+            },
+            result => panic!("unexpected {:?}", result)
         }
     }
 }
@@ -0,0 +1,489 @@
+use std::fs::{self, File};
+use std::io::{self, BufRead, Read, Write};
+use std::process::ExitCode;
+use std::env;
+
+use boolfuck::{bytecode, c, closures, rust, wasm, Bit, BitOrder, EofPolicy, Interpreter, Options, ParseError, PartialByte, Program, RuntimeError, Status};
+
+const USAGE: &str = "\
+usage: boolfuck [options] PROGRAM
+       boolfuck [options] repl
+       boolfuck --emit LANGUAGE PROGRAM
+
+Runs the Boolfuck program in the file PROGRAM, reading input from stdin
+and writing output to stdout.
+
+`repl` instead runs each line typed on stdin against the same tape and
+shows the cells around the pointer afterwards. A line that leaves a loop
+open asks for more lines until its brackets balance, and runs with them.
+When the program reads past the input typed so far it asks for another
+line of input. With --steps, the limit applies to each line. The REPL
+always runs on the interpreter, reads its input from the terminal and
+keeps an unfinished output byte for the next line, so --engine, --input
+and --partial-byte do not apply.
+
+--emit prints PROGRAM translated into LANGUAGE instead of running it:
+c for a C program that reads stdin and writes stdout, rust for a Rust
+module with a `pub fn run(input: &[u8]) -> Vec<u8>`, or wat or wasm for
+a WebAssembly module importing env.read_byte and env.write_byte.
+bytecode saves the program in a compact binary form, which PROGRAM may
+be in wherever a program is read, and boolfuck prints it as source.
+None of the options for running a program apply with --emit.
+
+options:
+    --input FILE            read input from FILE instead of stdin
+    --steps N               stop after N steps, where a step runs one
+                            optimised op: a single instruction, a run of
+                            moves, a loop idiom such as [+] or a
+                            translated Brainfuck command
+    --eof POLICY            what `,` reads once input runs out:
+                            zero (default), one, unchanged or error
+    --bit-order ORDER       bit order of input and output bytes:
+                            lsb (default) or msb
+    --partial-byte POLICY   what to do with an unfinished output byte:
+                            pad (default), drop or error
+    --tape-limit N          fail if the tape spans more than N cells
+    --engine ENGINE         what runs the program: interpreter (default),
+                            or closures to compile it to closures first,
+                            which cannot be used with --steps
+    --emit LANGUAGE         translate the program instead: boolfuck,
+                            bytecode, c, rust, wat or wasm
+    -h, --help              print this help
+
+exit status:
+    0   the program halted
+    1   bad arguments or an I/O error
+    2   the program does not parse
+    3   the program failed while running
+    4   the step limit or the tape limit ran out
+";
+
+const EXIT_USAGE: u8 = 1;
+const EXIT_PARSE: u8 = 2;
+const EXIT_RUNTIME: u8 = 3;
+const EXIT_LIMIT: u8 = 4;
+
+#[derive (PartialEq, Debug)]
+struct Args {
+    program: String,
+    input: Option<String>,
+    steps: Option<u64>,
+    engine: Engine,
+    options: Options
+}
+
+/// What runs the program.
+#[derive (PartialEq, Eq, Copy, Clone, Debug)]
+enum Engine {
+    Interpreter,
+    Closures
+}
+
+/// A language `--emit` translates into.
+#[derive (PartialEq, Eq, Copy, Clone, Debug)]
+enum Language {
+    Boolfuck,
+    Bytecode,
+    C,
+    Rust,
+    Wat,
+    Wasm
+}
+
+#[derive (PartialEq, Debug)]
+enum Command {
+    Run(Args),
+    Repl(Option<u64>, Options),
+    Emit(String, Language),
+    Help
+}
+
+/// How many cells on either side of the pointer the REPL shows.
+const REPL_RADIUS: isize = 8;
+
+/// Stdout flushed after every write, so each byte shows as soon as the
+/// program writes it rather than at the end of its line.
+struct Unbuffered(io::Stdout);
+
+impl Write for Unbuffered {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.0.write(buf)?;
+        self.0.flush()?;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+fn main() -> ExitCode {
+    let command = match parse_args(env::args().skip(1)) {
+        Ok(command) => command,
+        Err(message) => {
+            eprintln!("boolfuck: {}\n\n{}", message, USAGE);
+            return ExitCode::from(EXIT_USAGE);
+        }
+    };
+
+    match command {
+        Command::Help => {
+            print!("{}", USAGE);
+            ExitCode::SUCCESS
+        },
+        Command::Run(args) => match run(args) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err((code, message)) => {
+                eprintln!("boolfuck: {}", message);
+                ExitCode::from(code)
+            }
+        },
+        Command::Emit(program, language) => match emit(&program, language) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err((code, message)) => {
+                eprintln!("boolfuck: {}", message);
+                ExitCode::from(code)
+            }
+        },
+        Command::Repl(steps, options) => match repl(steps, options) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(e) => {
+                eprintln!("boolfuck: {}", e);
+                ExitCode::from(EXIT_USAGE)
+            }
+        }
+    }
+}
+
+/// Reads the program at `path`, as bytecode if it starts with the
+/// bytecode magic and as source otherwise.
+fn load(path: &str) -> Result<Program, (u8, String)> {
+    let bytes = fs::read(path).map_err(|e| (EXIT_USAGE, format!("{}: {}", path, e)))?;
+    if bytecode::is_bytecode(&bytes) {
+        return bytecode::decode(&bytes).map_err(|e| (EXIT_PARSE, format!("{}: {}", path, e)));
+    }
+
+    let code = String::from_utf8(bytes).map_err(|e| (EXIT_USAGE, format!("{}: {}", path, e)))?;
+    Program::parse(&code).map_err(|e| (EXIT_PARSE, format!("{}: {}", path, e)))
+}
+
+fn run(args: Args) -> Result<(), (u8, String)> {
+    let program = load(&args.program)?;
+
+    let input: Box<dyn Read + Send> = match &args.input {
+        Some(path) => Box::new(File::open(path).map_err(|e| (EXIT_USAGE, format!("{}: {}", path, e)))?),
+        None => Box::new(io::stdin())
+    };
+
+    // Only the interpreter knows which op it stopped at.
+    let (result, position) = match args.engine {
+        Engine::Interpreter => {
+            let mut interpreter = Interpreter::from_reader(program, input)
+                .with_output(Unbuffered(io::stdout()))
+                .with_options(args.options);
+
+            let result = match args.steps {
+                Some(steps) => interpreter.run_for(steps),
+                None => interpreter.interpret()
+            };
+            (result, interpreter.position())
+        },
+        Engine::Closures => {
+            let result = closures::Compiled::new(&program)
+                .run_to(input, Unbuffered(io::stdout()), args.options)
+                .map(|()| Status::Halted);
+            (result, None)
+        }
+    };
+
+    let located = |message: String| match position {
+        Some(position) => format!("{} at {}", message, position),
+        None => message
+    };
+
+    match result {
+        Ok(Status::Halted) => Ok(()),
+        Ok(Status::OutOfFuel) => Err((EXIT_LIMIT, located(format!("step limit of {} exhausted", args.steps.unwrap_or(0))))),
+        Ok(Status::NeedsInput) => unreachable!("the runner never suspends on input"),
+        Err(e @ RuntimeError::Io(_)) => Err((EXIT_USAGE, located(e.to_string()))),
+        Err(e @ RuntimeError::TapeLimit(_)) => Err((EXIT_LIMIT, located(e.to_string()))),
+        Err(e) => Err((EXIT_RUNTIME, located(e.to_string())))
+    }
+}
+
+fn emit(path: &str, language: Language) -> Result<(), (u8, String)> {
+    let program = load(path)?;
+
+    let source = match language {
+        Language::Boolfuck => program.instructions().iter().map(|instruction| instruction.to_char()).collect::<String>().into_bytes(),
+        Language::Bytecode => bytecode::encode(&program).map_err(|e| (EXIT_USAGE, format!("{}: {}", path, e)))?,
+        Language::C => c::generate(&program).into_bytes(),
+        Language::Rust => rust::generate(&program).into_bytes(),
+        Language::Wat => wasm::wat(&program).into_bytes(),
+        Language::Wasm => wasm::wasm(&program)
+    };
+
+    io::stdout().write_all(&source).map_err(|e| (EXIT_USAGE, e.to_string()))
+}
+
+fn repl(steps: Option<u64>, options: Options) -> io::Result<()> {
+    let options = Options { suspend_on_input: true, partial_byte: PartialByte::Keep, ..options };
+    let mut interpreter = Interpreter::new(Program::parse("").unwrap(), vec![])
+        .with_output(Unbuffered(io::stdout()))
+        .with_options(options);
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    // The lines of a loop that is still open.
+    let mut code = String::new();
+
+    loop {
+        eprint!("{}", if code.is_empty() { "> " } else { "... " });
+        let line = match lines.next() {
+            Some(line) => line?,
+            None if code.is_empty() => return Ok(()),
+            None => {
+                eprintln!("{}", Program::parse(&code).unwrap_err());
+                return Ok(());
+            }
+        };
+
+        code.push_str(&line);
+        code.push('\n');
+        match Program::parse(&code) {
+            Ok(program) => interpreter.append(program),
+            Err(ParseError::UnmatchedOpen(_)) => continue,
+            Err(e) => {
+                eprintln!("{}", e);
+                code.clear();
+                continue;
+            }
+        }
+        code.clear();
+
+        loop {
+            let result = match steps {
+                Some(steps) => interpreter.run_for(steps),
+                None => interpreter.interpret()
+            };
+
+            match result {
+                Ok(Status::NeedsInput) => {
+                    eprint!("input> ");
+                    match lines.next() {
+                        Some(input) => {
+                            interpreter.feed(input?.as_bytes());
+                            interpreter.feed(b"\n");
+                        },
+                        None => interpreter.close_input()
+                    }
+                },
+                Ok(Status::Halted) => break,
+                Ok(Status::OutOfFuel) => {
+                    eprintln!("step limit of {} exhausted", steps.unwrap_or(0));
+                    interpreter.halt();
+                    break;
+                },
+                Err(e) => {
+                    eprintln!("{}", e);
+                    interpreter.halt();
+                    break;
+                }
+            }
+        }
+
+        io::stdout().flush()?;
+        eprintln!("{}", show_tape(&interpreter, REPL_RADIUS));
+    }
+}
+
+/// Renders the visited cells within `radius` of the pointer, with the cell
+/// under the pointer in brackets.
+fn show_tape(interpreter: &Interpreter, radius: isize) -> String {
+    let pointer = interpreter.pointer();
+    let visited = interpreter.tape().visited();
+    let start = (pointer - radius).max(*visited.start());
+    let end = (pointer + radius).min(*visited.end());
+
+    let cells: Vec<_> = (start ..= end)
+        .map(|position| {
+            let bit = match interpreter.tape().get(position) {
+                Bit::Zero => '0',
+                Bit::One => '1'
+            };
+
+            if position == pointer {
+                format!("[{}]", bit)
+            } else {
+                bit.to_string()
+            }
+        })
+        .collect();
+
+    format!("cells {}..={}: {} (pointer at {})", start, end, cells.join(" "), pointer)
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Command, String> {
+    let mut program = None;
+    let mut input = None;
+    let mut steps = None;
+    let mut emit = None;
+    let mut engine = Engine::Interpreter;
+    let mut options = Options::default();
+    // The options that only apply to running a program.
+    let mut run_options = vec![];
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+
+        if arg.starts_with("--") && arg != "--help" && arg != "--emit" {
+            run_options.push(arg.clone());
+        }
+
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "--input" => input = Some(value()?),
+            "--steps" => steps = Some(parse_number(&arg, &value()?)?),
+            "--eof" => options.eof = match value()?.as_str() {
+                "zero" => EofPolicy::Zero,
+                "one" => EofPolicy::One,
+                "unchanged" => EofPolicy::Unchanged,
+                "error" => EofPolicy::Error,
+                other => return Err(format!("unknown EOF policy '{}'", other))
+            },
+            "--bit-order" => options.bit_order = match value()?.as_str() {
+                "lsb" => BitOrder::LsbFirst,
+                "msb" => BitOrder::MsbFirst,
+                other => return Err(format!("unknown bit order '{}'", other))
+            },
+            "--partial-byte" => options.partial_byte = match value()?.as_str() {
+                "pad" => PartialByte::Pad,
+                "drop" => PartialByte::Drop,
+                "error" => PartialByte::Error,
+                other => return Err(format!("unknown partial byte policy '{}'", other))
+            },
+            "--tape-limit" => options.tape_limit = Some(parse_number(&arg, &value()?)?),
+            "--engine" => engine = match value()?.as_str() {
+                "interpreter" => Engine::Interpreter,
+                "closures" => Engine::Closures,
+                other => return Err(format!("unknown engine '{}'", other))
+            },
+            "--emit" => emit = Some(match value()?.as_str() {
+                "boolfuck" => Language::Boolfuck,
+                "bytecode" => Language::Bytecode,
+                "c" => Language::C,
+                "rust" => Language::Rust,
+                "wat" => Language::Wat,
+                "wasm" => Language::Wasm,
+                other => return Err(format!("unknown language '{}'", other))
+            }),
+            _ if arg.starts_with('-') => return Err(format!("unknown option '{}'", arg)),
+            _ if program.is_none() => program = Some(arg),
+            _ => return Err(format!("unexpected argument '{}'", arg))
+        }
+    }
+
+    let program = program.ok_or("no program given")?;
+    if let Some(language) = emit {
+        if let Some(option) = run_options.first() {
+            return Err(format!("{} cannot be used with --emit", option));
+        }
+
+        return Ok(Command::Emit(program, language));
+    }
+
+    if program == "repl" {
+        if let Some(option) = run_options.iter().find(|option| ["--input", "--engine", "--partial-byte"].contains(&option.as_str())) {
+            return Err(format!("{} cannot be used with repl", option));
+        }
+
+        return Ok(Command::Repl(steps, options));
+    }
+
+    if engine == Engine::Closures && steps.is_some() {
+        return Err("--steps needs the interpreter engine".to_string());
+    }
+
+    Ok(Command::Run(Args { program, input, steps, engine, options }))
+}
+
+fn parse_number<T: std::str::FromStr>(option: &str, value: &str) -> Result<T, String> {
+    value.parse().map_err(|_| format!("{} expects a number, got '{}'", option, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Command, String> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_parse_args() {
+        assert_eq!(parse(&["hello.bf"]), Ok(Command::Run(Args {
+            program: "hello.bf".to_string(),
+            input: None,
+            steps: None,
+            engine: Engine::Interpreter,
+            options: Options::default()
+        })));
+
+        assert_eq!(
+            parse(&[
+                "--input", "in.txt", "--steps", "100", "--eof", "error", "--bit-order", "msb",
+                "--partial-byte", "drop", "--tape-limit", "64", "prog.bf"
+            ]),
+            Ok(Command::Run(Args {
+                program: "prog.bf".to_string(),
+                input: Some("in.txt".to_string()),
+                steps: Some(100),
+                engine: Engine::Interpreter,
+                options: Options {
+                    eof: EofPolicy::Error,
+                    bit_order: BitOrder::MsbFirst,
+                    partial_byte: PartialByte::Drop,
+                    tape_limit: Some(64),
+                    ..Options::default()
+                }
+            }))
+        );
+
+        assert_eq!(parse(&["prog.bf", "--help"]), Ok(Command::Help));
+        assert!(matches!(parse(&["--engine", "closures", "prog.bf"]), Ok(Command::Run(Args { engine: Engine::Closures, .. }))));
+        assert_eq!(parse(&["--steps", "5", "repl"]), Ok(Command::Repl(Some(5), Options::default())));
+        assert_eq!(parse(&["--emit", "c", "prog.bf"]), Ok(Command::Emit("prog.bf".to_string(), Language::C)));
+        assert_eq!(parse(&["--emit", "rust", "prog.bf"]), Ok(Command::Emit("prog.bf".to_string(), Language::Rust)));
+        assert_eq!(parse(&["--emit", "wasm", "prog.bf"]), Ok(Command::Emit("prog.bf".to_string(), Language::Wasm)));
+        assert_eq!(parse(&["--emit", "bytecode", "prog.bf"]), Ok(Command::Emit("prog.bf".to_string(), Language::Bytecode)));
+    }
+
+    #[test]
+    fn test_show_tape() {
+        let mut interpreter = Interpreter::new(Program::parse("+>>+<").unwrap(), vec![]);
+        interpreter.interpret().unwrap();
+        assert_eq!(show_tape(&interpreter, 8), "cells 0..=2: 1 [0] 1 (pointer at 1)");
+        assert_eq!(show_tape(&interpreter, 0), "cells 1..=1: [0] (pointer at 1)");
+
+        let mut interpreter = Interpreter::new(Program::parse("<<<<+").unwrap(), vec![]);
+        interpreter.interpret().unwrap();
+        assert_eq!(show_tape(&interpreter, 2), "cells -4..=-2: [1] 0 0 (pointer at -4)");
+    }
+
+    #[test]
+    fn test_parse_args_errors() {
+        assert_eq!(parse(&[]), Err("no program given".to_string()));
+        assert_eq!(parse(&["a.bf", "b.bf"]), Err("unexpected argument 'b.bf'".to_string()));
+        assert_eq!(parse(&["--steps"]), Err("--steps needs a value".to_string()));
+        assert_eq!(parse(&["--steps", "many"]), Err("--steps expects a number, got 'many'".to_string()));
+        assert_eq!(parse(&["--eof", "maybe"]), Err("unknown EOF policy 'maybe'".to_string()));
+        assert_eq!(parse(&["--emit", "cobol", "a.bf"]), Err("unknown language 'cobol'".to_string()));
+        assert_eq!(parse(&["--verbose"]), Err("unknown option '--verbose'".to_string()));
+        assert_eq!(parse(&["--input", "x", "repl"]), Err("--input cannot be used with repl".to_string()));
+        assert_eq!(parse(&["--engine", "closures", "repl"]), Err("--engine cannot be used with repl".to_string()));
+        assert_eq!(parse(&["--partial-byte", "drop", "repl"]), Err("--partial-byte cannot be used with repl".to_string()));
+        assert_eq!(parse(&["--emit", "c", "--tape-limit", "8", "a.bf"]), Err("--tape-limit cannot be used with --emit".to_string()));
+        assert_eq!(parse(&["--input", "x", "--emit", "wat", "a.bf"]), Err("--input cannot be used with --emit".to_string()));
+        assert_eq!(parse(&["--engine", "jit", "a.bf"]), Err("unknown engine 'jit'".to_string()));
+        assert_eq!(parse(&["--engine", "closures", "--steps", "5", "a.bf"]), Err("--steps needs the interpreter engine".to_string()));
+    }
+}
@@ -0,0 +1,272 @@
+use std::io::{self, Write};
+use super::*;
+
+/// What to do with the bits of an unfinished byte when a program halts.
+#[derive (PartialEq, Eq, Copy, Clone, Debug, Default)]
+pub enum PartialByte {
+    /// Fill the missing high bits with zeros and emit the byte.
+    #[default]
+    Pad,
+    /// Discard the bits.
+    Drop,
+    /// Fail with [`RuntimeError::PartialByte`].
+    Error,
+    /// Keep the bits pending, so instructions appended later with
+    /// [`Interpreter::append`] can complete the byte.
+    Keep
+}
+
+enum Sink {
+    Buffer(Vec<u8>),
+    Writer(Box<dyn Write + Send>)
+}
+
+/// The bits written by `;`, packed into bytes and emitted as soon as each
+/// byte is complete.
+pub(crate) struct Output {
+    pending: Vec<Bit>,
+    sink: Sink
+}
+
+impl Output {
+    /// Output collected in memory.
+    pub fn buffer() -> Output {
+        Output {
+            pending: vec![],
+            sink: Sink::Buffer(vec![])
+        }
+    }
+
+    pub fn to_writer(writer: impl Write + Send + 'static) -> Output {
+        Output {
+            pending: vec![],
+            sink: Sink::Writer(Box::new(writer))
+        }
+    }
+
+    /// Writes `bit`, or leaves the output as it was if the byte it
+    /// completes cannot be written, so the write can be retried.
+    pub fn write_bit(&mut self, bit: Bit, order: BitOrder) -> io::Result<()> {
+        self.pending.push(bit);
+        if self.pending.len() == 8 {
+            let byte = order.u8_from_bits(&self.pending);
+            if let Err(e) = self.write_byte(byte) {
+                self.pending.pop();
+                return Err(e);
+            }
+            self.pending.clear();
+        }
+
+        Ok(())
+    }
+
+    /// Writes the eight `bits` of a byte op, or none of them if the byte
+    /// they complete cannot be written, so the op can be retried.
+    pub fn write_bits(&mut self, bits: [Bit; 8], order: BitOrder) -> io::Result<()> {
+        // Eight bits complete exactly one byte, so nothing is written
+        // before the write that can fail.
+        let pending = self.pending.len();
+        for bit in bits {
+            if let Err(e) = self.write_bit(bit, order) {
+                self.pending.truncate(pending);
+                return Err(e);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Deals with a trailing partial byte according to `policy` and
+    /// flushes the writer.
+    pub fn finish(&mut self, policy: PartialByte, order: BitOrder) -> Result<(), RuntimeError> {
+        if !self.pending.is_empty() {
+            match policy {
+                PartialByte::Pad => {
+                    let byte = order.u8_from_bits(&self.pending);
+                    self.write_byte(byte)?;
+                },
+                PartialByte::Drop => {},
+                PartialByte::Error => {
+                    // The complete bytes before it still go out.
+                    self.flush()?;
+                    return Err(RuntimeError::PartialByte(self.pending.len()));
+                },
+                PartialByte::Keep => return self.flush()
+            }
+
+            self.pending.clear();
+        }
+
+        self.flush()
+    }
+
+    fn flush(&mut self) -> Result<(), RuntimeError> {
+        if let Sink::Writer(writer) = &mut self.sink {
+            writer.flush()?;
+        }
+
+        Ok(())
+    }
+
+    /// The bytes collected so far; always empty when writing to a writer.
+    pub fn bytes(&self) -> &[u8] {
+        match &self.sink {
+            Sink::Buffer(bytes) => bytes,
+            Sink::Writer(_) => &[]
+        }
+    }
+
+    /// The bits of the byte currently being assembled.
+    pub fn pending(&self) -> &[Bit] {
+        &self.pending
+    }
+
+    fn write_byte(&mut self, byte: u8) -> io::Result<()> {
+        match &mut self.sink {
+            Sink::Buffer(bytes) => {
+                bytes.push(byte);
+                Ok(())
+            },
+            Sink::Writer(writer) => writer.write_all(&[byte])
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use super::*;
+
+    #[derive (Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn write_bits(output: &mut Output, bits: &[Bit]) {
+        for &bit in bits {
+            output.write_bit(bit, BitOrder::LsbFirst).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_streams_complete_bytes() {
+        let shared = Shared::default();
+        let mut output = Output::to_writer(shared.clone());
+
+        write_bits(&mut output, &utils::bits_from_u8(65)[.. 7]);
+        assert!(shared.0.lock().unwrap().is_empty());
+        write_bits(&mut output, &utils::bits_from_u8(65)[7 ..]);
+        assert_eq!(*shared.0.lock().unwrap(), vec![65]);
+        assert!(output.bytes().is_empty());
+    }
+
+    /// A writer whose first write would block.
+    #[derive (Clone, Default)]
+    struct Flaky(Shared, bool);
+
+    impl Write for Flaky {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            if !self.1 {
+                self.1 = true;
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            self.0.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_retry() {
+        use Bit::*;
+        let flaky = Flaky::default();
+        let mut output = Output::to_writer(flaky.clone());
+        write_bits(&mut output, &[One; 7]);
+        assert!(output.write_bit(One, BitOrder::LsbFirst).is_err());
+        assert_eq!(output.pending(), &[One; 7]);
+        output.write_bit(One, BitOrder::LsbFirst).unwrap();
+        assert_eq!(*flaky.0.0.lock().unwrap(), vec![255]);
+
+        let flaky = Flaky::default();
+        let mut output = Output::to_writer(flaky.clone());
+        write_bits(&mut output, &[One, Zero]);
+        let byte = [One, Zero, Zero, Zero, Zero, Zero, Zero, One];
+        assert!(output.write_bits(byte, BitOrder::LsbFirst).is_err());
+        assert_eq!(output.pending(), &[One, Zero]);
+        output.write_bits(byte, BitOrder::LsbFirst).unwrap();
+        assert_eq!(*flaky.0.0.lock().unwrap(), vec![0b101]);
+        assert_eq!(output.pending(), &[Zero, One]);
+    }
+
+    #[test]
+    fn test_pad() {
+        use Bit::*;
+        let mut output = Output::buffer();
+        write_bits(&mut output, &[One, Zero, One]);
+        assert_eq!(output.pending(), &[One, Zero, One]);
+        output.finish(PartialByte::Pad, BitOrder::LsbFirst).unwrap();
+        assert_eq!(output.bytes(), &[5]);
+        assert!(output.pending().is_empty());
+    }
+
+    #[test]
+    fn test_pad_msb_first() {
+        use Bit::*;
+        let mut output = Output::buffer();
+        output.write_bit(One, BitOrder::MsbFirst).unwrap();
+        output.finish(PartialByte::Pad, BitOrder::MsbFirst).unwrap();
+        assert_eq!(output.bytes(), &[0x80]);
+    }
+
+    #[test]
+    fn test_drop() {
+        use Bit::*;
+        let mut output = Output::buffer();
+        write_bits(&mut output, &utils::bits_from_u8(200));
+        write_bits(&mut output, &[One]);
+        output.finish(PartialByte::Drop, BitOrder::LsbFirst).unwrap();
+        assert_eq!(output.bytes(), &[200]);
+    }
+
+    #[test]
+    fn test_keep() {
+        use Bit::*;
+        let mut output = Output::buffer();
+        write_bits(&mut output, &[One, One]);
+        output.finish(PartialByte::Keep, BitOrder::LsbFirst).unwrap();
+        assert!(output.bytes().is_empty());
+        write_bits(&mut output, &[Zero; 6]);
+        assert_eq!(output.bytes(), &[3]);
+    }
+
+    #[test]
+    fn test_error() {
+        use Bit::*;
+        let mut output = Output::buffer();
+        write_bits(&mut output, &[One, One]);
+        assert!(matches!(output.finish(PartialByte::Error, BitOrder::LsbFirst), Err(RuntimeError::PartialByte(2))));
+
+        let mut output = Output::buffer();
+        write_bits(&mut output, &utils::bits_from_u8(1));
+        output.finish(PartialByte::Error, BitOrder::LsbFirst).unwrap();
+        assert_eq!(output.bytes(), &[1]);
+
+        let shared = Shared::default();
+        let mut output = Output::to_writer(io::BufWriter::new(shared.clone()));
+        write_bits(&mut output, &utils::bits_from_u8(1));
+        write_bits(&mut output, &[One]);
+        assert!(matches!(output.finish(PartialByte::Error, BitOrder::LsbFirst), Err(RuntimeError::PartialByte(1))));
+        assert_eq!(*shared.0.lock().unwrap(), vec![1]);
+    }
+}
@@ -0,0 +1,252 @@
+use std::str::FromStr;
+use super::*;
+
+/// A Boolfuck program whose brackets are known to balance, compiled into
+/// the optimised [`Op`]s the interpreter runs, with their jump targets
+/// already resolved.
+///
+/// A program is immutable once built, so it can be shared between threads
+/// behind an `Arc` and run by any number of [`Interpreter`]s.
+///
+/// It also keeps which instruction each op came from and, when it was
+/// parsed from source, where each instruction is in it, so ops can be
+/// traced back to the source. A program extended with others keeps which
+/// of them each instruction came from, since each has its own source.
+#[derive (Clone, Debug)]
+pub struct Program {
+    instructions: Vec<Instruction>,
+    ops: Vec<Op>,
+    positions: Option<Vec<Position>>,
+    sources: Vec<usize>,
+    /// The index of the first instruction of each program appended by
+    /// [`Program::extend`], starting with this one's.
+    parts: Vec<usize>
+}
+
+/// Programs are equal if they run the same ops, wherever they came from.
+impl PartialEq for Program {
+    fn eq(&self, other: &Program) -> bool {
+        self.instructions == other.instructions && self.ops == other.ops
+    }
+}
+
+impl Eq for Program {}
+
+impl Program {
+    /// Parses `code`, ignoring every character that is not a command.
+    pub fn parse(code: &str) -> Result<Program, ParseError> {
+        let (instructions, positions): (Vec<_>, _) = parser::parse_with_positions(code)?.into_iter().unzip();
+        let (ops, sources) = ir::optimize(&instructions);
+        Ok(Program { instructions, ops, positions: Some(positions), sources, parts: vec![0] })
+    }
+
+    /// Builds a program from an instruction stream. It has no source, so
+    /// it has no positions.
+    ///
+    /// Positions in the error treat each instruction as one character on
+    /// the first line.
+    pub fn new(instructions: Vec<Instruction>) -> Result<Program, ParseError> {
+        Program::check(&instructions)?;
+        let (ops, sources) = ir::optimize(&instructions);
+        Ok(Program { instructions, ops, positions: None, sources, parts: vec![0] })
+    }
+
+    /// Builds a program that runs one op per instruction, without any
+    /// optimisation. This is the reference the optimised form must match.
+    pub fn unoptimized(instructions: Vec<Instruction>) -> Result<Program, ParseError> {
+        Program::check(&instructions)?;
+        let (ops, sources) = ir::lower(&instructions);
+        Ok(Program { instructions, ops, positions: None, sources, parts: vec![0] })
+    }
+
+    fn check(instructions: &[Instruction]) -> Result<(), ParseError> {
+        let positions = (0 ..).map(|i| Position { offset: i, line: 1, column: i + 1 });
+        parser::check_brackets(instructions.iter().copied().zip(positions))
+    }
+
+    /// Appends `other` to this program. Both are balanced on their own, so
+    /// the jump targets of `other` are shifted into place rather than
+    /// recomputed. The positions of its instructions stay those in its
+    /// own source, which [`Program::part`] tells apart, and the result has
+    /// none unless both programs have them.
+    pub fn extend(&mut self, other: Program) {
+        let mut ops = other.ops;
+        ir::relocate(&mut ops, self.ops.len());
+        let offset = self.instructions.len();
+        self.sources.extend(other.sources.into_iter().map(|source| source + offset));
+        self.parts.extend(other.parts.into_iter().map(|start| start + offset));
+        self.instructions.extend(other.instructions);
+        self.positions = match (self.positions.take(), other.positions) {
+            (Some(mut positions), Some(other)) => {
+                positions.extend(other);
+                Some(positions)
+            },
+            _ => None
+        };
+        self.ops.extend(ops);
+    }
+
+    /// The instructions as written, one per command in the source.
+    pub fn instructions(&self) -> &[Instruction] {
+        &self.instructions
+    }
+
+    /// The ops the interpreter runs.
+    pub fn ops(&self) -> &[Op] {
+        &self.ops
+    }
+
+    /// Where each instruction is in the source, or `None` if the program
+    /// was not parsed from source. In an extended program each position is
+    /// in the source of the part the instruction came from.
+    pub fn positions(&self) -> Option<&[Position]> {
+        self.positions.as_deref()
+    }
+
+    /// Which of the programs joined by [`Program::extend`] the instruction
+    /// at `instruction` came from, counting this one's first as zero.
+    pub fn part(&self, instruction: usize) -> usize {
+        // Empty parts share a start with the part after them.
+        self.parts.partition_point(|&start| start <= instruction) - 1
+    }
+
+    /// The index of the instruction the op at `op` came from.
+    pub fn source(&self, op: usize) -> usize {
+        self.sources[op]
+    }
+
+    /// Where in the source the op at `op` came from, or `None` if the
+    /// program was not parsed from source.
+    pub fn op_position(&self, op: usize) -> Option<Position> {
+        self.positions().map(|positions| positions[self.sources[op]])
+    }
+
+    pub fn len(&self) -> usize {
+        self.instructions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instructions.is_empty()
+    }
+}
+
+impl FromStr for Program {
+    type Err = ParseError;
+
+    fn from_str(code: &str) -> Result<Program, ParseError> {
+        Program::parse(code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        use Instruction::*;
+        let program: Program = "+[>;]".parse().unwrap();
+        assert_eq!(program.instructions(), &[Flip, SkipRight, MoveRight, Write, SkipLeft]);
+        assert_eq!(program.len(), 5);
+    }
+
+    #[test]
+    fn test_new() {
+        use Instruction::*;
+        assert!(Program::new(vec![SkipRight, SkipLeft]).is_ok());
+        assert_eq!(
+            Program::new(vec![Flip, SkipLeft]),
+            Err(ParseError::UnmatchedClose(Position { offset: 1, line: 1, column: 2 }))
+        );
+        assert_eq!(
+            Program::new(vec![SkipRight]),
+            Err(ParseError::UnmatchedOpen(Position { offset: 0, line: 1, column: 1 }))
+        );
+    }
+
+    #[test]
+    fn test_unoptimized() {
+        use Instruction::*;
+        let program = Program::unoptimized(vec![Flip, Flip, MoveRight]).unwrap();
+        assert_eq!(program.ops(), &[Op::Flip, Op::Flip, Op::straight(1)]);
+        assert!(Program::unoptimized(vec![SkipLeft]).is_err());
+
+        let program = Program::new(vec![Flip, Flip, MoveRight]).unwrap();
+        assert_eq!(program.ops(), &[Op::straight(1)]);
+        assert_eq!(program.instructions(), &[Flip, Flip, MoveRight]);
+    }
+
+    #[test]
+    fn test_jump_targets() {
+        let program = Program::parse("[[]+[]]").unwrap();
+        assert_eq!(
+            program.ops(),
+            &[Op::SkipRight(6), Op::SkipRight(2), Op::SkipLeft(1), Op::Flip, Op::SkipRight(5), Op::SkipLeft(4), Op::SkipLeft(0)]
+        );
+    }
+
+    #[test]
+    fn test_extend() {
+        let mut program = Program::parse("+[>]").unwrap();
+        program.extend(Program::parse("[<[;]]").unwrap());
+        assert_eq!(program.instructions(), Program::parse("+[>][<[;]]").unwrap().instructions());
+        assert_eq!(program.ops()[2], Op::SkipRight(7));
+        assert_eq!(program.ops()[4], Op::SkipRight(6));
+        assert_eq!(program.ops()[6], Op::SkipLeft(4));
+
+        let mut program = Program::parse(">>").unwrap();
+        program.extend(Program::parse("<<[>>]").unwrap());
+        assert_eq!(program.ops(), &[Op::straight(2), Op::straight(-2), Op::SkipRight(4), Op::straight(2), Op::SkipLeft(2)]);
+    }
+
+    #[test]
+    fn test_positions() {
+        let program = Program::parse("+\n>>>\n  [+]").unwrap();
+        assert_eq!(program.ops(), &[Op::Flip, Op::straight(3), Op::SetZero]);
+        assert_eq!(program.op_position(1), Some(Position { offset: 2, line: 2, column: 1 }));
+        assert_eq!(program.op_position(2), Some(Position { offset: 8, line: 3, column: 3 }));
+        assert_eq!(program.source(2), 4);
+
+        let program = Program::new(Program::parse("+[;]").unwrap().instructions().to_vec()).unwrap();
+        assert_eq!(program.op_position(3), None);
+        assert_eq!(program.positions(), None);
+        assert_eq!(program, Program::parse("+ [ ; ]").unwrap());
+
+        let mut program = Program::parse(";\n;").unwrap();
+        program.extend(Program::parse(" >>").unwrap());
+        assert_eq!(program.source(2), 2);
+        assert_eq!(program.op_position(2), Some(Position { offset: 1, line: 1, column: 2 }));
+
+        assert_eq!(program.part(program.source(2)), 1);
+
+        program.extend(Program::parse("").unwrap());
+        program.extend(Program::new(vec![Instruction::Flip]).unwrap());
+        assert_eq!(program.op_position(0), None);
+        assert_eq!(program.part(0), 0);
+        assert_eq!(program.part(1), 0);
+        assert_eq!(program.part(2), 1);
+        assert_eq!(program.part(4), 3);
+    }
+
+    #[test]
+    fn test_byte_op_positions() {
+        let code = brainfuck::translate("+\n.").unwrap();
+        let program = Program::parse(&code).unwrap();
+        let write = program.ops().iter().position(|op| matches!(op, Op::WriteByte(_))).unwrap();
+        let start = program.source(write);
+        assert_eq!(code[program.positions().unwrap()[start].offset ..], *brainfuck::translation('.'));
+        for (op, &source) in program.sources.iter().enumerate() {
+            assert!(source < program.len(), "op {} has no instruction", op);
+        }
+    }
+
+    #[test]
+    fn test_send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Program>();
+
+        // A resumable interpreter can move between threads.
+        fn assert_send<T: Send>() {}
+        assert_send::<Interpreter>();
+    }
+}
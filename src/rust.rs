@@ -0,0 +1,205 @@
+//! Translating programs into Rust.
+//!
+//! The generated code is a module with no dependencies whose only public
+//! item is `pub fn run(input: &[u8]) -> Vec<u8>`. It behaves like
+//! [`boolfuck`](crate::boolfuck): bytes are split into bits least
+//! significant bit first, reading past the end of the input yields zero
+//! bits and a trailing partial byte is padded with zero bits.
+//!
+//! ```
+//! let program = boolfuck::Program::parse("+[;>]").unwrap();
+//! let code = boolfuck::rust::generate(&program);
+//! assert!(code.contains("pub fn run(input: &[u8]) -> Vec<u8>"));
+//! ```
+
+use std::fmt::Write;
+use super::*;
+
+const RUNTIME: &str = r#"
+struct State<'a> {
+    right: Vec<u64>,
+    left: Vec<u64>,
+    input: &'a [u8],
+    input_bits: usize,
+    output: Vec<u8>,
+    output_bits: usize
+}
+
+#[allow(dead_code)]
+impl<'a> State<'a> {
+    fn word(&mut self, p: isize) -> (&mut u64, u64) {
+        let (words, index) = if p >= 0 { (&mut self.right, p as usize) } else { (&mut self.left, (-1 - p) as usize) };
+        if index / 64 >= words.len() {
+            words.resize(index / 64 + 1, 0);
+        }
+        (&mut words[index / 64], 1 << (index % 64))
+    }
+
+    fn get(&mut self, p: isize) -> bool {
+        let (word, mask) = self.word(p);
+        *word & mask != 0
+    }
+
+    fn set(&mut self, p: isize, bit: bool) {
+        let (word, mask) = self.word(p);
+        if bit { *word |= mask } else { *word &= !mask }
+    }
+
+    fn flip(&mut self, p: isize) {
+        let (word, mask) = self.word(p);
+        *word ^= mask;
+    }
+
+    fn read(&mut self) -> bool {
+        let bit = match self.input.get(self.input_bits / 8) {
+            Some(byte) => byte >> (self.input_bits % 8) & 1 != 0,
+            None => false
+        };
+        self.input_bits += 1;
+        bit
+    }
+
+    fn write(&mut self, bit: bool) {
+        if self.output_bits % 8 == 0 {
+            self.output.push(0);
+        }
+        if bit {
+            *self.output.last_mut().unwrap() |= 1 << (self.output_bits % 8);
+        }
+        self.output_bits += 1;
+    }
+
+    /// Whether the guard cells at `p` and `p + 9` are both zero.
+    fn byte_clear(&mut self, p: isize) -> bool {
+        !self.get(p) && !self.get(p + 9)
+    }
+
+    fn byte(&mut self, p: isize) -> u8 {
+        (0 .. 8).fold(0, |byte, i| byte | (self.get(p + i) as u8) << i)
+    }
+
+    fn set_byte(&mut self, p: isize, byte: u8) {
+        for i in 0 .. 8 {
+            self.set(p + i, byte >> i & 1 != 0);
+        }
+    }
+}
+"#;
+
+/// Translates `program` into a Rust module. Loops become `while` loops,
+/// so the code reads much like the program.
+pub fn generate(program: &Program) -> String {
+    let mut code = String::from("// Generated from a Boolfuck program.\n\n");
+    code.push_str("#[allow(unused_mut)]\npub fn run(input: &[u8]) -> Vec<u8> {\n");
+    code.push_str("    let mut s = State { right: vec![], left: vec![], input, input_bits: 0, output: vec![], output_bits: 0 };\n");
+    code.push_str("    let mut p: isize = 0;\n");
+    block(program.ops(), 0, program.ops().len(), 1, &mut code);
+    code.push_str("    let _ = p;\n");
+    code.push_str("    s.output\n}\n");
+    code.push_str(RUNTIME);
+    code
+}
+
+/// Writes the ops from `start` up to `end`, which hold whole loops.
+fn block(ops: &[Op], start: usize, end: usize, depth: usize, code: &mut String) {
+    let indent = "    ".repeat(depth);
+    let mut i = start;
+
+    while i < end {
+        let line = match ops[i] {
+            Op::Flip => "s.flip(p);".to_string(),
+            Op::Read => "let bit = s.read(); s.set(p, bit);".to_string(),
+            Op::Write => "let bit = s.get(p); s.write(bit);".to_string(),
+            Op::Move { by, .. } => format!("p += {};", by),
+            Op::SkipRight(target) => {
+                writeln!(code, "{}while s.get(p) {{", indent).unwrap();
+                block(ops, i + 1, target, depth + 1, code);
+                writeln!(code, "{}}}", indent).unwrap();
+                i = target + 1;
+                continue;
+            },
+            Op::SkipLeft(_) => unreachable!("loops are written whole"),
+            Op::SetZero => "s.set(p, false);".to_string(),
+            Op::ScanRight => "while s.get(p) { p += 1; }".to_string(),
+            Op::ScanLeft => "while s.get(p) { p -= 1; }".to_string(),
+            Op::IncByte(len) | Op::DecByte(len) => {
+                let delta = if let Op::IncByte(_) = ops[i] { 1 } else { 255 };
+                writeln!(code, "{}if s.byte_clear(p) {{", indent).unwrap();
+                writeln!(code, "{}    let byte = s.byte(p + 1).wrapping_add({}); s.set_byte(p + 1, byte);", indent, delta).unwrap();
+                writeln!(code, "{}}} else {{", indent).unwrap();
+                block(ops, i + 1, i + 1 + len, depth + 1, code);
+                writeln!(code, "{}}}", indent).unwrap();
+                i += 1 + len;
+                continue;
+            },
+            // The rest of the byte ops jump into and out of their
+            // translations, which `while` loops cannot do, so only the
+            // translations are written.
+            Op::ReadByte(_) | Op::WriteByte(_) | Op::ByteLoopStart(..) | Op::ByteLoopEnd(..) => {
+                i += 1;
+                continue;
+            }
+        };
+
+        writeln!(code, "{}{}", indent, line).unwrap();
+        i += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, process};
+    use testing::*;
+
+    /// Builds one binary holding the generated module of each program,
+    /// which runs the one named by its argument on stdin, and runs each on
+    /// its input. Returns `None` if there is no `rustc`.
+    fn run_rust(cases: &[(Program, Vec<u8>)]) -> Option<Vec<Vec<u8>>> {
+        let dir = env::temp_dir().join(format!("boolfuck-rust-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("main.rs");
+        let binary = dir.join("main");
+
+        let mut code = String::new();
+        for (i, (program, _)) in cases.iter().enumerate() {
+            writeln!(code, "mod program{} {{\n{}\n}}", i, generate(program)).unwrap();
+        }
+        code.push_str("fn main() {\n    use std::io::{Read, Write};\n    let mut input = vec![];\n");
+        code.push_str("    std::io::stdin().read_to_end(&mut input).unwrap();\n");
+        code.push_str("    let output = match std::env::args().nth(1).unwrap().as_str() {\n");
+        for i in 0 .. cases.len() {
+            writeln!(code, "        \"{}\" => program{}::run(&input),", i, i).unwrap();
+        }
+        code.push_str("        _ => unreachable!()\n    };\n    std::io::stdout().write_all(&output).unwrap();\n}\n");
+        fs::write(&source, code).unwrap();
+
+        let compiled = process::Command::new("rustc")
+            .args(["--edition", "2018", "-D", "warnings", "-o"])
+            .arg(&binary)
+            .arg(&source)
+            .status()
+            .ok()?;
+        assert!(compiled.success(), "rustc failed on {}", source.display());
+
+        let outputs = cases
+            .iter()
+            .enumerate()
+            .map(|(i, (_, input))| run_piped(process::Command::new(&binary).arg(i.to_string()), input))
+            .collect();
+        fs::remove_dir_all(&dir).unwrap();
+        outputs
+    }
+
+    #[test]
+    fn test_generate() {
+        let code = generate(&Program::parse("+[>;]<").unwrap());
+        assert!(code.contains("    while s.get(p) {\n        p += 1;\n        let bit = s.get(p); s.write(bit);\n    }\n"));
+        assert!(code.contains("p += -1;"));
+    }
+
+    #[test]
+    fn test_matches_interpreter() {
+        assert_generator_matches(run_rust);
+    }
+}
@@ -0,0 +1,534 @@
+use std::io;
+use std::ops::{Range, RangeInclusive};
+use super::*;
+use input::Input;
+use output::Output;
+
+const WORD_BITS: usize = 64;
+
+/// A bit tape that grows in both directions.
+///
+/// Cells are packed 64 to a machine word. Cells at and to the right of the
+/// origin live in one word vector and cells to the left in another, so
+/// growing in either direction is an amortised O(1) push. The tape also
+/// remembers the range of cells that have been visited, which is what
+/// [`Tape::bits`] shows.
+///
+/// Tapes are equal if they have visited the same cells and hold the same
+/// bits, however much storage they have grown.
+#[derive (Clone, Debug)]
+pub struct Tape {
+    right: Vec<u64>,
+    left: Vec<u64>,
+    start: isize,
+    end: isize
+}
+
+impl PartialEq for Tape {
+    fn eq(&self, other: &Tape) -> bool {
+        self.visited() == other.visited()
+            && trim(&self.right) == trim(&other.right)
+            && trim(&self.left) == trim(&other.left)
+    }
+}
+
+impl Eq for Tape {}
+
+impl Default for Tape {
+    fn default() -> Tape {
+        Tape::new()
+    }
+}
+
+impl Tape {
+    /// A tape of zeros whose only visited cell is the origin.
+    pub fn new() -> Tape {
+        Tape {
+            right: vec![],
+            left: vec![],
+            start: 0,
+            end: 0
+        }
+    }
+
+    pub fn get(&self, position: isize) -> Bit {
+        let (words, index) = self.locate(position);
+        match words.get(index / WORD_BITS) {
+            Some(word) if word & (1 << (index % WORD_BITS)) != 0 => Bit::One,
+            _ => Bit::Zero
+        }
+    }
+
+    pub fn set(&mut self, position: isize, bit: Bit) {
+        let (word, mask) = self.word_mut(position);
+        match bit {
+            Bit::Zero => *word &= !mask,
+            Bit::One => *word |= mask
+        }
+    }
+
+    pub fn flip(&mut self, position: isize) {
+        let (word, mask) = self.word_mut(position);
+        *word ^= mask;
+    }
+
+    /// Marks `position` and every cell between it and the visited range as
+    /// visited.
+    pub fn visit(&mut self, position: isize) {
+        self.start = self.start.min(position);
+        self.end = self.end.max(position);
+    }
+
+    /// Visits `cells`, unless the visited cells would then span more than
+    /// `limit`. Cells already visited never break it, so a limit of zero
+    /// only forbids moving.
+    pub(crate) fn visit_within(&mut self, cells: RangeInclusive<isize>, limit: Option<usize>) -> Result<(), RuntimeError> {
+        if *cells.start() >= self.start && *cells.end() <= self.end {
+            return Ok(());
+        }
+
+        check_limit(self.len_with_range(cells.clone()), limit)?;
+        self.visit(*cells.start());
+        self.visit(*cells.end());
+        Ok(())
+    }
+
+    /// The visited cells, leftmost first.
+    pub fn visited(&self) -> RangeInclusive<isize> {
+        self.start ..= self.end
+    }
+
+    /// The number of visited cells.
+    pub fn len(&self) -> usize {
+        (self.end - self.start) as usize + 1
+    }
+
+    /// The number of visited cells once `position` is visited too.
+    pub fn len_with(&self, position: isize) -> usize {
+        self.len_with_range(position ..= position)
+    }
+
+    /// The number of visited cells once `cells` are visited too.
+    pub fn len_with_range(&self, cells: RangeInclusive<isize>) -> usize {
+        (self.end.max(*cells.end()) - self.start.min(*cells.start())) as usize + 1
+    }
+
+    /// Always false: the origin counts as visited.
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+
+    /// The eight cells from `position` rightwards as a byte, least
+    /// significant bit first.
+    pub fn byte(&self, position: isize) -> u8 {
+        if position >= 0 {
+            return bits(&self.right, position as usize, 8) as u8;
+        }
+
+        // The cells left of the origin are stored outwards from it, so
+        // their bits come out reversed.
+        let (left, right) = split_byte(position);
+        let low = reverse(bits(&self.left, left.start, left.len()), left.len());
+        let high = bits(&self.right, right.start, right.len());
+        (low | high << left.len()) as u8
+    }
+
+    /// Stores `byte` in the eight cells from `position` rightwards, least
+    /// significant bit first.
+    pub fn set_byte(&mut self, position: isize, byte: u8) {
+        if position >= 0 {
+            return set_bits(&mut self.right, position as usize, 8, byte as u64);
+        }
+
+        let (left, right) = split_byte(position);
+        let byte = byte as u64;
+        set_bits(&mut self.left, left.start, left.len(), reverse(byte, left.len()));
+        set_bits(&mut self.right, right.start, right.len(), byte >> left.len());
+    }
+
+    /// Whether the translation of a byte op can be skipped with the
+    /// pointer at `position`: `limit` allows visiting `reach` cells right
+    /// of it and, if `guarded`, the cells either side of the byte are
+    /// zero, as the Brainfuck translation always leaves them.
+    ///
+    /// If so, the cells the translation would visit are visited.
+    fn byte_fast_path(&mut self, position: isize, reach: isize, guarded: bool, limit: Option<usize>) -> bool {
+        let clear = !guarded || self.get(position) == Bit::Zero && self.get(position + 9) == Bit::Zero;
+        clear && self.visit_within(position ..= position + reach, limit).is_ok()
+    }
+
+    /// Runs `IncByte` or `DecByte` with the pointer at `position` by
+    /// adding `delta` to the byte right of it, and returns whether the
+    /// fast path applied.
+    pub(crate) fn add_to_byte(&mut self, position: isize, delta: u8, limit: Option<usize>) -> bool {
+        if !self.byte_fast_path(position, 9, true, limit) {
+            return false;
+        }
+
+        let byte = self.byte(position + 1).wrapping_add(delta);
+        self.set_byte(position + 1, byte);
+        true
+    }
+
+    /// Runs `ReadByte` with the pointer at `position`, and returns whether
+    /// the fast path applied. It only applies when the whole byte can be
+    /// read, so running out of input is left to the translation.
+    pub(crate) fn read_byte(&mut self, position: isize, input: &mut Input, order: BitOrder, limit: Option<usize>) -> io::Result<bool> {
+        if !input.has_bits(8, order)? || !self.byte_fast_path(position, 8, false, limit) {
+            return Ok(false);
+        }
+
+        for i in 1 ..= 8 {
+            let bit = input.read_bit(order)?.expect("the input has a whole byte");
+            self.set(position + i, bit);
+        }
+
+        Ok(true)
+    }
+
+    /// Runs `WriteByte` with the pointer at `position`, and returns
+    /// whether the fast path applied.
+    pub(crate) fn write_byte(&mut self, position: isize, output: &mut Output, order: BitOrder, limit: Option<usize>) -> io::Result<bool> {
+        if !self.byte_fast_path(position, 8, false, limit) {
+            return Ok(false);
+        }
+
+        let bits = std::array::from_fn(|i| self.get(position + 1 + i as isize));
+        output.write_bits(bits, order)?;
+        Ok(true)
+    }
+
+    /// The byte a translated Brainfuck bracket at `position` tests, or
+    /// `None` if the fast path does not apply.
+    pub(crate) fn loop_byte(&mut self, position: isize, limit: Option<usize>) -> Option<u8> {
+        if self.byte_fast_path(position, 9, true, limit) {
+            Some(self.byte(position + 1))
+        } else {
+            None
+        }
+    }
+
+    /// The visited cells as bits, leftmost first.
+    pub fn bits(&self) -> Vec<Bit> {
+        self.visited().map(|position| self.get(position)).collect()
+    }
+
+    /// The nearest position at or right of `from` holding a zero, found a
+    /// word at a time.
+    pub fn scan_right(&self, from: isize) -> isize {
+        let mut position = from;
+
+        // Left of the origin, cells are stored outwards from it, so scanning
+        // right walks down through the bits of the left words.
+        while position < 0 {
+            let index = (-1 - position) as usize;
+            let word = match self.left.get(index / WORD_BITS) {
+                Some(word) => *word,
+                None => return position
+            };
+
+            let bit = index % WORD_BITS;
+            let zeros = !word & low_bits(bit);
+            if zeros != 0 {
+                let nearest = WORD_BITS - 1 - zeros.leading_zeros() as usize;
+                return position + (bit - nearest) as isize;
+            }
+
+            position += bit as isize + 1;
+        }
+
+        loop {
+            let index = position as usize;
+            let word = match self.right.get(index / WORD_BITS) {
+                Some(word) => *word,
+                None => return position
+            };
+
+            let bit = index % WORD_BITS;
+            let zeros = !word >> bit;
+            if zeros != 0 {
+                return position + zeros.trailing_zeros() as isize;
+            }
+
+            position += (WORD_BITS - bit) as isize;
+        }
+    }
+
+    /// The nearest position at or left of `from` holding a zero, found a
+    /// word at a time.
+    pub fn scan_left(&self, from: isize) -> isize {
+        let mut position = from;
+
+        while position >= 0 {
+            let index = position as usize;
+            let word = match self.right.get(index / WORD_BITS) {
+                Some(word) => *word,
+                None => return position
+            };
+
+            let bit = index % WORD_BITS;
+            let zeros = !word & low_bits(bit);
+            if zeros != 0 {
+                let nearest = WORD_BITS - 1 - zeros.leading_zeros() as usize;
+                return position - (bit - nearest) as isize;
+            }
+
+            position -= bit as isize + 1;
+        }
+
+        loop {
+            let index = (-1 - position) as usize;
+            let word = match self.left.get(index / WORD_BITS) {
+                Some(word) => *word,
+                None => return position
+            };
+
+            let bit = index % WORD_BITS;
+            let zeros = !word >> bit;
+            if zeros != 0 {
+                return position - zeros.trailing_zeros() as isize;
+            }
+
+            position -= (WORD_BITS - bit) as isize;
+        }
+    }
+
+    fn locate(&self, position: isize) -> (&Vec<u64>, usize) {
+        if position >= 0 {
+            (&self.right, position as usize)
+        } else {
+            (&self.left, (-1 - position) as usize)
+        }
+    }
+
+    fn word_mut(&mut self, position: isize) -> (&mut u64, u64) {
+        let (words, index) = if position >= 0 {
+            (&mut self.right, position as usize)
+        } else {
+            (&mut self.left, (-1 - position) as usize)
+        };
+
+        let word = index / WORD_BITS;
+        if word >= words.len() {
+            words.resize(word + 1, 0);
+        }
+
+        (&mut words[word], 1 << (index % WORD_BITS))
+    }
+}
+
+/// Fails with [`RuntimeError::TapeLimit`] if a tape spanning `len` cells
+/// breaks `limit`.
+pub(crate) fn check_limit(len: usize, limit: Option<usize>) -> Result<(), RuntimeError> {
+    match limit {
+        Some(limit) if len > limit => Err(RuntimeError::TapeLimit(limit)),
+        _ => Ok(())
+    }
+}
+
+/// The indices among the left and the right words of the eight cells
+/// from `position` rightwards.
+fn split_byte(position: isize) -> (Range<usize>, Range<usize>) {
+    let left = (-position).clamp(0, 8) as usize;
+    let start = position.max(0) as usize;
+    ((-position) as usize - left .. (-position) as usize, start .. start + 8 - left)
+}
+
+/// The `len` bits of `words` from bit `index` up, in the low bits.
+fn bits(words: &[u64], index: usize, len: usize) -> u64 {
+    if len == 0 {
+        return 0;
+    }
+
+    let (word, bit) = (index / WORD_BITS, index % WORD_BITS);
+    let mut value = words.get(word).map_or(0, |word| word >> bit);
+    if bit + len > WORD_BITS {
+        value |= words.get(word + 1).map_or(0, |word| word << (WORD_BITS - bit));
+    }
+
+    value & ((1 << len) - 1)
+}
+
+/// Stores the low `len` bits of `value` in `words` from bit `index` up,
+/// growing `words` if need be.
+fn set_bits(words: &mut Vec<u64>, index: usize, len: usize, value: u64) {
+    if len == 0 {
+        return;
+    }
+
+    let (word, bit) = (index / WORD_BITS, index % WORD_BITS);
+    let last = (index + len - 1) / WORD_BITS;
+    if last >= words.len() {
+        words.resize(last + 1, 0);
+    }
+
+    let mask = (1 << len) - 1;
+    words[word] = words[word] & !(mask << bit) | value << bit;
+    if last > word {
+        let shift = WORD_BITS - bit;
+        words[last] = words[last] & !(mask >> shift) | value >> shift;
+    }
+}
+
+/// The low `len` bits of `value` in reverse order.
+fn reverse(value: u64, len: usize) -> u64 {
+    if len == 0 { 0 } else { value.reverse_bits() >> (WORD_BITS - len) }
+}
+
+/// `words` without the zero words at its end, which hold no set cells.
+fn trim(words: &[u64]) -> &[u64] {
+    let len = words.iter().rposition(|&word| word != 0).map_or(0, |last| last + 1);
+    &words[.. len]
+}
+
+/// A mask of bits `0 ..= bit`.
+fn low_bits(bit: usize) -> u64 {
+    if bit == WORD_BITS - 1 {
+        !0
+    } else {
+        (1 << (bit + 1)) - 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use testing::Rng;
+
+    #[test]
+    fn test_get_set() {
+        use Bit::*;
+
+        let mut tape = Tape::new();
+        for &position in &[0, 1, 63, 64, 1000, -1, -64, -65, -1000] {
+            assert_eq!(tape.get(position), Zero);
+            tape.set(position, One);
+            assert_eq!(tape.get(position), One);
+            assert_eq!(tape.get(position + 1), Zero);
+            assert_eq!(tape.get(position - 1), Zero);
+            tape.set(position, Zero);
+            assert_eq!(tape.get(position), Zero);
+        }
+    }
+
+    #[test]
+    fn test_flip() {
+        use Bit::*;
+
+        let mut tape = Tape::new();
+        tape.flip(-3);
+        tape.flip(70);
+        assert_eq!(tape.get(-3), One);
+        assert_eq!(tape.get(70), One);
+        tape.flip(-3);
+        assert_eq!(tape.get(-3), Zero);
+    }
+
+    #[test]
+    fn test_packed() {
+        let mut tape = Tape::new();
+        tape.set(-128, Bit::One);
+        tape.set(127, Bit::One);
+        assert_eq!(tape.left.len(), 2);
+        assert_eq!(tape.right.len(), 2);
+    }
+
+    #[test]
+    fn test_scan() {
+        let mut tape = Tape::new();
+        assert_eq!(tape.scan_right(5), 5);
+        assert_eq!(tape.scan_left(-5), -5);
+
+        for position in -70 .. 130 {
+            tape.set(position, Bit::One);
+        }
+
+        assert_eq!(tape.scan_right(-100), -100);
+        assert_eq!(tape.scan_right(-70), 130);
+        assert_eq!(tape.scan_right(0), 130);
+        assert_eq!(tape.scan_left(129), -71);
+        assert_eq!(tape.scan_left(-1), -71);
+    }
+
+    #[test]
+    fn test_scan_matches_naive() {
+        let mut rng = Rng::new(7);
+        let mut tape = Tape::new();
+        for position in -300 .. 300 {
+            if rng.below(8) != 0 {
+                tape.set(position, Bit::One);
+            }
+        }
+
+        for from in -320 .. 320 {
+            let right = (from ..).find(|&position| tape.get(position) == Bit::Zero).unwrap();
+            let left = (-1000 ..= from).rev().find(|&position| tape.get(position) == Bit::Zero).unwrap();
+            assert_eq!(tape.scan_right(from), right, "scan right from {}", from);
+            assert_eq!(tape.scan_left(from), left, "scan left from {}", from);
+        }
+    }
+
+    #[test]
+    fn test_byte() {
+        let mut tape = Tape::new();
+        for &position in &[0, 60, -4, -70] {
+            tape.set_byte(position, 0b1000_0110);
+            assert_eq!(tape.byte(position), 0b1000_0110);
+            assert_eq!(tape.get(position), Bit::Zero);
+            assert_eq!(tape.get(position + 1), Bit::One);
+            assert_eq!(tape.get(position + 7), Bit::One);
+            assert_eq!(tape.byte(position + 1), 0b0100_0011);
+            tape.set_byte(position, 0);
+        }
+    }
+
+    #[test]
+    fn test_eq() {
+        let mut tape = Tape::new();
+        tape.set(200, Bit::One);
+        tape.set(-70, Bit::One);
+        assert_ne!(tape, Tape::new());
+        tape.set(200, Bit::Zero);
+        tape.set(-70, Bit::Zero);
+        assert_eq!(tape, Tape::new());
+
+        tape.visit(1);
+        assert_ne!(tape, Tape::new());
+    }
+
+    #[test]
+    fn test_byte_matches_bits() {
+        let mut rng = Rng::new(3);
+        let mut tape = Tape::new();
+        for position in -140 .. 140 {
+            let byte = rng.next() as u8;
+            tape.set_byte(position, byte);
+            let bits = (0 .. 8).fold(0, |bits, i| bits | ((tape.get(position + i) == Bit::One) as u8) << i);
+            assert_eq!(bits, byte, "byte at {}", position);
+            assert_eq!(tape.byte(position), byte, "byte at {}", position);
+        }
+    }
+
+    #[test]
+    fn test_bits() {
+        use Bit::*;
+
+        let mut tape = Tape::new();
+        assert_eq!(tape.bits(), vec![Zero]);
+
+        tape.visit(2);
+        tape.set(2, One);
+        assert_eq!(tape.bits(), vec![Zero, Zero, One]);
+
+        tape.visit(-2);
+        tape.set(-1, One);
+        assert_eq!(tape.visited(), -2 ..= 2);
+        assert_eq!(tape.len(), 5);
+        assert_eq!(tape.len_with(0), 5);
+        assert_eq!(tape.len_with(4), 7);
+        assert_eq!(tape.len_with(-4), 7);
+        assert_eq!(tape.len_with_range(-4 ..= 4), 9);
+        assert_eq!(tape.len_with_range(-1 ..= 1), 5);
+        assert_eq!(tape.bits(), vec![Zero, One, Zero, Zero, One]);
+    }
+}
@@ -0,0 +1,280 @@
+//! Helpers for the tests that check optimised execution against the
+//! reference semantics, and every other backend against the interpreter.
+
+use std::ops::RangeInclusive;
+use std::process;
+use std::io::Write;
+use super::*;
+
+/// Boolfuck that writes `Hello, world!` and a newline.
+pub const HELLO: &str = ";;;+;+;;+;+;+;+;+;+;;+;;+;;;+;;+;+;;+;;;+;;+;+;;+;+;;;;+;+;;+;;;+;;+;+;+;;;;;;;+;+;;+;;;+;+;;;+;+;;;;+;+;;+;;+;+;;+;;;+;;;+;;+;+;;+;;;+;+;;+;;+;+;+;;;;+;+;;;+;+;+;";
+
+/// The input random programs are run on.
+pub const INPUT: [u8; 4] = [0x5a, 0xff, 0x00, 0x81];
+
+/// A xorshift generator, so random tests are reproducible.
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1)
+    }
+
+    pub fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+/// A random program of roughly `len` instructions with balanced brackets.
+pub fn random_program(seed: u64, len: usize) -> Vec<Instruction> {
+    use Instruction::*;
+
+    let mut rng = Rng::new(seed);
+    let mut instructions = vec![];
+    let mut depth = 0;
+
+    for _ in 0 .. len {
+        let instruction = match rng.below(10) {
+            0 | 1 => Flip,
+            2 | 3 => MoveLeft,
+            4 | 5 => MoveRight,
+            6 => Read,
+            7 => Write,
+            8 => SkipRight,
+            _ if depth > 0 => SkipLeft,
+            _ => Flip
+        };
+
+        match instruction {
+            SkipRight => depth += 1,
+            SkipLeft => depth -= 1,
+            _ => {}
+        }
+
+        instructions.push(instruction);
+    }
+
+    instructions.extend((0 .. depth).map(|_| SkipLeft));
+    instructions
+}
+
+/// Random Brainfuck source of roughly `len` commands with balanced
+/// brackets.
+pub fn random_brainfuck(seed: u64, len: usize) -> String {
+    let mut rng = Rng::new(seed);
+    let mut code = String::new();
+    let mut depth = 0;
+
+    for _ in 0 .. len {
+        let command = match rng.below(12) {
+            0 ..= 2 => '+',
+            3 | 4 => '-',
+            5 | 6 => '>',
+            7 | 8 => '<',
+            9 => if rng.below(2) == 0 { ',' } else { '.' },
+            10 => '[',
+            _ if depth > 0 => ']',
+            _ => '+'
+        };
+
+        match command {
+            '[' => depth += 1,
+            ']' => depth -= 1,
+            _ => {}
+        }
+
+        code.push(command);
+    }
+
+    code.extend((0 .. depth).map(|_| ']'));
+    code
+}
+
+/// The Boolfuck translation of random Brainfuck like
+/// [`random_brainfuck`], with stray Boolfuck between some of its macros.
+/// The stray code breaks the layout the macros expect, so byte ops have
+/// to fall back to their translation.
+pub fn disturbed_brainfuck(seed: u64, len: usize) -> String {
+    let stray = ["+", ">", "<", "<+>", ">>>>>>>>>+<<<<<<<<<", ";"];
+    let mut rng = Rng::new(seed);
+    random_brainfuck(seed, len)
+        .chars()
+        .map(|command| {
+            let mut code = brainfuck::translation(command).to_string();
+            if rng.below(4) == 0 {
+                code.push_str(stray[rng.below(stray.len())]);
+            }
+            code
+        })
+        .collect()
+}
+
+/// Everything observable about a finished run.
+#[derive (PartialEq, Debug)]
+pub struct Outcome {
+    /// The error the run stopped with, if any.
+    pub error: Option<String>,
+    pub output: Vec<u8>,
+    pub pointer: isize,
+    pub visited: RangeInclusive<isize>,
+    pub ones: Vec<isize>
+}
+
+/// Runs `program` on `input`, or returns `None` if it does not halt
+/// within `steps`.
+pub fn run(program: Program, input: &[u8], steps: u64) -> Option<Outcome> {
+    run_with(program, input, steps, Options::default())
+}
+
+/// Runs `program` on `input` like [`run`], with `options`. A run that
+/// fails counts as finished.
+pub fn run_with(program: Program, input: &[u8], steps: u64, options: Options) -> Option<Outcome> {
+    let mut interpreter = Interpreter::from_reader(program, io::Cursor::new(input.to_vec())).with_options(options);
+    let error = match interpreter.run_for(steps) {
+        Ok(Status::Halted) => None,
+        Ok(_) => return None,
+        Err(e) => Some(e.to_string())
+    };
+
+    let tape = interpreter.tape();
+    Some(Outcome {
+        error,
+        output: interpreter.get_output().to_vec(),
+        pointer: interpreter.pointer(),
+        visited: tape.visited(),
+        ones: tape.visited().filter(|&position| tape.get(position) == Bit::One).collect()
+    })
+}
+
+/// Checks that `program` behaves like the reference semantics of
+/// `instructions` whenever the reference run finishes, with the tape
+/// unlimited and with tape limits small enough to be broken.
+pub fn assert_same_behaviour(instructions: &[Instruction], program: Program) {
+    const STEPS: u64 = 20_000;
+
+    let reference = Program::unoptimized(instructions.to_vec()).unwrap();
+    let limited = |limit| Options { tape_limit: Some(limit), ..Options::default() };
+    for options in [Options::default(), limited(12), limited(40)] {
+        if let Some(expected) = run_with(reference.clone(), &INPUT, STEPS, options) {
+            let outcome = run_with(program.clone(), &INPUT, STEPS, options);
+            assert_eq!(outcome.as_ref(), Some(&expected), "program {:?} with {:?}", instructions, options);
+        }
+    }
+}
+
+/// Programs every backend must run like the interpreter, with their
+/// input: text, a tape that grows both ways, input that runs out and
+/// translated Brainfuck.
+pub fn fixtures() -> Vec<(Program, Vec<u8>)> {
+    let codes = [
+        (HELLO.to_string(), &b""[..]),
+        (format!("{}+;>+;{}+;+[<]>;", "<".repeat(70), ">".repeat(200)), b""),
+        (",;".repeat(18), b"ok"),
+        (brainfuck::translate(",[--------------------------------.,]").unwrap(), b"shout")
+    ];
+
+    codes.iter().map(|(code, input)| (Program::parse(code).unwrap(), input.to_vec())).collect()
+}
+
+/// The random programs of roughly `len` instructions from the first
+/// `count` seeds that halt on [`INPUT`].
+pub fn halting_programs(count: u64, len: usize) -> Vec<Program> {
+    (0 .. count)
+        .map(|seed| Program::new(random_program(seed, len)).unwrap())
+        .filter(|program| run(program.clone(), &INPUT, 20_000).is_some())
+        .collect()
+}
+
+/// The bytes the interpreter writes running `program` on `input`, or the
+/// error it stops with.
+pub fn interpret(program: &Program, input: &[u8], options: Options) -> Result<Vec<u8>, RuntimeError> {
+    let mut interpreter = Interpreter::from_reader(program.clone(), io::Cursor::new(input.to_vec())).with_options(options);
+    interpreter.interpret().map(|_| interpreter.get_output().to_vec())
+}
+
+/// An engine that runs a program in this process, as [`interpret`] does.
+pub type Engine<'a> = &'a dyn Fn(&Program, &[u8], Options) -> Result<Vec<u8>, RuntimeError>;
+
+/// Checks that `engine` writes what the interpreter writes, or fails the
+/// same way.
+pub fn assert_same_output(engine: Engine, program: &Program, input: &[u8], options: Options) {
+    let expected = interpret(program, input, options);
+    let output = engine(program, input, options);
+    assert_eq!(format!("{:?}", output), format!("{:?}", expected), "program {:?} with {:?}", program.instructions(), options);
+}
+
+/// Checks that `engine` runs the fixtures, every EOF and partial byte
+/// policy, tape limits and random programs like the interpreter.
+pub fn assert_engine_matches(engine: Engine) {
+    for (program, input) in fixtures() {
+        assert_same_output(engine, &program, &input, Options::default());
+    }
+
+    let reads = Program::parse(&format!("+{}", ",;".repeat(11))).unwrap();
+    for eof in [EofPolicy::Zero, EofPolicy::One, EofPolicy::Unchanged, EofPolicy::Error] {
+        for partial_byte in [PartialByte::Pad, PartialByte::Drop, PartialByte::Error] {
+            let options = Options { eof, partial_byte, bit_order: BitOrder::MsbFirst, ..Options::default() };
+            assert_same_output(engine, &reads, b"a", options);
+        }
+    }
+
+    let limited = Options { tape_limit: Some(3), ..Options::default() };
+    for code in [">>+<<<", ">>;<<;", "+>+>+<<[>]", ">><<"] {
+        assert_same_output(engine, &Program::parse(code).unwrap(), b"", limited);
+    }
+
+    let unmovable = Options { tape_limit: Some(0), ..Options::default() };
+    for code in ["+;", "[>]", "+[<]", ">"] {
+        assert_same_output(engine, &Program::parse(code).unwrap(), b"", unmovable);
+    }
+
+    for program in halting_programs(200, 60) {
+        for tape_limit in [None, Some(12)] {
+            assert_same_output(engine, &program, &INPUT, Options { tape_limit, ..Options::default() });
+        }
+    }
+
+    for seed in 0 .. 50 {
+        let program = Program::parse(&brainfuck::translate(&random_brainfuck(seed, 30)).unwrap()).unwrap();
+        if run(program.clone(), &INPUT, 200_000).is_some() {
+            assert_same_output(engine, &program, &INPUT, Options::default());
+        }
+    }
+}
+
+/// Checks that a code generator writes what the interpreter writes on
+/// the fixtures and random programs. `build_and_run` gets every program with its
+/// input and returns the output of each, or `None` if the tools to build
+/// the generated code are missing.
+pub fn assert_generator_matches(build_and_run: impl FnOnce(&[(Program, Vec<u8>)]) -> Option<Vec<Vec<u8>>>) {
+    let mut cases = fixtures();
+    cases.extend(halting_programs(20, 40).into_iter().map(|program| (program, INPUT.to_vec())));
+
+    let outputs = match build_and_run(&cases) {
+        Some(outputs) => outputs,
+        None => return
+    };
+
+    for ((program, input), output) in cases.iter().zip(outputs) {
+        let expected = interpret(program, input, Options::default()).unwrap();
+        assert_eq!(output, expected, "program {:?}", program.instructions());
+    }
+}
+
+/// Runs `command` with `input` on its stdin and returns what it wrote to
+/// stdout, or `None` if it cannot be started.
+pub fn run_piped(command: &mut process::Command, input: &[u8]) -> Option<Vec<u8>> {
+    let mut child = command.stdin(process::Stdio::piped()).stdout(process::Stdio::piped()).spawn().ok()?;
+    // A program can halt before reading all of its input.
+    let _ = child.stdin.take().unwrap().write_all(input);
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "{:?} failed", command);
+    Some(output.stdout)
+}
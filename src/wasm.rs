@@ -0,0 +1,496 @@
+//! Translating programs into WebAssembly.
+//!
+//! The module imports `env.read_byte`, which returns the next input byte or
+//! -1 at end of input, and `env.write_byte`, and exports its memory and a
+//! `run` function that runs the program once. Bytes are split into bits
+//! least significant bit first, reading past the end of the input yields
+//! zero bits and a trailing partial byte is padded with zero bits, as
+//! [`boolfuck`](crate::boolfuck) does.
+//!
+//! The tape is packed 8 cells to a byte of linear memory, with cells right
+//! of the origin at even bits and cells left of it at odd bits, so the tape
+//! only ever grows upwards. The same module comes as text or binary.
+//!
+//! ```
+//! let program = boolfuck::Program::parse("+[;>]").unwrap();
+//! assert!(boolfuck::wasm::wat(&program).contains("(module"));
+//! assert!(boolfuck::wasm::wasm(&program).starts_with(b"\0asm"));
+//! ```
+
+use std::fmt::Write;
+use super::*;
+
+/// The WebAssembly instructions the module needs.
+#[derive (PartialEq, Eq, Copy, Clone, Debug)]
+enum Instr {
+    Unreachable,
+    Block,
+    Loop,
+    If,
+    End,
+    Br(u32),
+    BrIf(u32),
+    Return,
+    Call(u32),
+    LocalGet(u32),
+    LocalSet(u32),
+    LocalTee(u32),
+    GlobalGet(u32),
+    GlobalSet(u32),
+    Load8,
+    Store8,
+    MemorySize,
+    MemoryGrow,
+    Const(i32),
+    Eqz,
+    Eq,
+    LtS,
+    GeU,
+    Add,
+    Sub,
+    And,
+    Or,
+    Xor,
+    Shl,
+    ShrS,
+    ShrU
+}
+
+use Instr::*;
+
+/// A function signature, as a count of i32 parameters and results.
+type Signature = (usize, usize);
+
+struct Function {
+    name: &'static str,
+    signature: Signature,
+    /// Names of the parameters, then of the other locals.
+    locals: &'static [&'static str],
+    body: Vec<Instr>
+}
+
+const IMPORTS: [(&str, Signature); 2] = [("read_byte", (0, 1)), ("write_byte", (1, 0))];
+const GLOBALS: [&str; 4] = ["in_byte", "in_bits", "out_byte", "out_bits"];
+
+// Function indices, counting the imports first.
+const READ_BYTE: u32 = 0;
+const WRITE_BYTE: u32 = 1;
+const CELL: u32 = 2;
+const GET: u32 = 3;
+const FLIP: u32 = 4;
+const SET: u32 = 5;
+const READ_BIT: u32 = 6;
+const WRITE_BIT: u32 = 7;
+
+const IN_BYTE: u32 = 0;
+const IN_BITS: u32 = 1;
+const OUT_BYTE: u32 = 2;
+const OUT_BITS: u32 = 3;
+
+/// The functions of the module, `run` last.
+fn functions(program: &Program) -> Vec<Function> {
+    // The bit of memory holding cell `p`, growing memory to reach it.
+    let cell = vec![
+        LocalGet(0), Const(1), Shl, LocalGet(0), Const(31), ShrS, Xor, LocalTee(1),
+        Const(19), ShrU, LocalTee(2), MemorySize, GeU, If,
+            LocalGet(2), MemorySize, Sub, Const(1), Add, MemoryGrow, Const(-1), Eq, If,
+                Unreachable,
+            End,
+        End,
+        LocalGet(1)
+    ];
+    let get = vec![
+        LocalGet(0), Call(CELL), LocalTee(1), Const(3), ShrU, Load8, LocalGet(1), Const(7), And, ShrU, Const(1), And
+    ];
+    let flip = vec![
+        LocalGet(0), Call(CELL), LocalTee(1), Const(3), ShrU, LocalTee(2),
+        LocalGet(2), Load8, Const(1), LocalGet(1), Const(7), And, Shl, Xor, Store8
+    ];
+    let set = vec![
+        LocalGet(0), Call(GET), LocalGet(1), Xor, If,
+            LocalGet(0), Call(FLIP),
+        End
+    ];
+    let read_bit = vec![
+        GlobalGet(IN_BITS), Eqz, If,
+            Call(READ_BYTE), LocalTee(0), Const(0), LtS, If,
+                Const(0), Return,
+            End,
+            LocalGet(0), GlobalSet(IN_BYTE), Const(8), GlobalSet(IN_BITS),
+        End,
+        GlobalGet(IN_BYTE), Const(1), And,
+        GlobalGet(IN_BYTE), Const(1), ShrU, GlobalSet(IN_BYTE),
+        GlobalGet(IN_BITS), Const(1), Sub, GlobalSet(IN_BITS)
+    ];
+    let write_bit = vec![
+        GlobalGet(OUT_BYTE), LocalGet(0), GlobalGet(OUT_BITS), Shl, Or, GlobalSet(OUT_BYTE),
+        GlobalGet(OUT_BITS), Const(1), Add, LocalTee(0), GlobalSet(OUT_BITS),
+        LocalGet(0), Const(8), Eq, If,
+            GlobalGet(OUT_BYTE), Call(WRITE_BYTE), Const(0), GlobalSet(OUT_BYTE), Const(0), GlobalSet(OUT_BITS),
+        End
+    ];
+
+    let mut run = translate(program.ops());
+    run.extend([GlobalGet(OUT_BITS), If, GlobalGet(OUT_BYTE), Call(WRITE_BYTE), End]);
+
+    vec![
+        Function { name: "cell", signature: (1, 1), locals: &["p", "index", "page"], body: cell },
+        Function { name: "get", signature: (1, 1), locals: &["p", "index"], body: get },
+        Function { name: "flip", signature: (1, 0), locals: &["p", "index", "address"], body: flip },
+        Function { name: "set", signature: (2, 0), locals: &["p", "value"], body: set },
+        Function { name: "read_bit", signature: (0, 1), locals: &["byte"], body: read_bit },
+        Function { name: "write_bit", signature: (1, 0), locals: &["bit"], body: write_bit },
+        Function { name: "run", signature: (0, 0), locals: &["p"], body: run }
+    ]
+}
+
+/// The body of `run`. Loops become a `loop` in a `block`, which the loop
+/// breaks out of once the cell under the pointer is zero.
+fn translate(ops: &[Op]) -> Vec<Instr> {
+    const P: u32 = 0;
+    let mut body = vec![];
+
+    for op in ops {
+        match *op {
+            Op::Flip => body.extend([LocalGet(P), Call(FLIP)]),
+            Op::Read => body.extend([LocalGet(P), Call(READ_BIT), Call(SET)]),
+            Op::Write => body.extend([LocalGet(P), Call(GET), Call(WRITE_BIT)]),
+            Op::Move { by, .. } => body.extend([LocalGet(P), Const(by as i32), Add, LocalSet(P)]),
+            Op::SkipRight(_) => body.extend([Block, Loop, LocalGet(P), Call(GET), Eqz, BrIf(1)]),
+            Op::SkipLeft(_) => body.extend([Br(0), End, End]),
+            Op::SetZero => body.extend([LocalGet(P), Const(0), Call(SET)]),
+            Op::ScanRight | Op::ScanLeft => {
+                let by = if *op == Op::ScanRight { 1 } else { -1 };
+                body.extend([Block, Loop, LocalGet(P), Call(GET), Eqz, BrIf(1), LocalGet(P), Const(by), Add, LocalSet(P), Br(0), End, End]);
+            },
+            Op::IncByte(_) | Op::DecByte(_) | Op::ReadByte(_) | Op::WriteByte(_) | Op::ByteLoopStart(..) | Op::ByteLoopEnd(..) => {}
+        }
+    }
+
+    body
+}
+
+fn signature_text(signature: Signature, names: &[&str]) -> String {
+    let mut text = String::new();
+    for name in &names[.. signature.0] {
+        write!(text, " (param ${} i32)", name).unwrap();
+    }
+    if signature.1 == 1 {
+        text.push_str(" (result i32)");
+    }
+    text
+}
+
+/// Translates `program` into a module in the WebAssembly text format.
+pub fn wat(program: &Program) -> String {
+    let functions = functions(program);
+    let function_name = |index: u32| match index as usize {
+        i if i < IMPORTS.len() => IMPORTS[i].0,
+        i => functions[i - IMPORTS.len()].name
+    };
+
+    let mut text = String::from(";; Generated from a Boolfuck program.\n(module\n");
+    for (name, signature) in IMPORTS {
+        writeln!(text, "  (import \"env\" \"{}\" (func ${}{}))", name, name, signature_text(signature, &["byte"])).unwrap();
+    }
+    text.push_str("  (memory (export \"memory\") 1)\n");
+    for name in GLOBALS {
+        writeln!(text, "  (global ${} (mut i32) (i32.const 0))", name).unwrap();
+    }
+
+    for function in &functions {
+        let export = if function.name == "run" { " (export \"run\")" } else { "" };
+        writeln!(text, "  (func ${}{}{}", function.name, export, signature_text(function.signature, function.locals)).unwrap();
+        for name in &function.locals[function.signature.0 ..] {
+            writeln!(text, "    (local ${} i32)", name).unwrap();
+        }
+
+        let mut depth = 2;
+        for &instr in &function.body {
+            if instr == End {
+                depth -= 1;
+            }
+            let line = match instr {
+                Unreachable => "unreachable".to_string(),
+                Block => "block".to_string(),
+                Loop => "loop".to_string(),
+                If => "if".to_string(),
+                End => "end".to_string(),
+                Br(depth) => format!("br {}", depth),
+                BrIf(depth) => format!("br_if {}", depth),
+                Return => "return".to_string(),
+                Call(index) => format!("call ${}", function_name(index)),
+                LocalGet(index) => format!("local.get ${}", function.locals[index as usize]),
+                LocalSet(index) => format!("local.set ${}", function.locals[index as usize]),
+                LocalTee(index) => format!("local.tee ${}", function.locals[index as usize]),
+                GlobalGet(index) => format!("global.get ${}", GLOBALS[index as usize]),
+                GlobalSet(index) => format!("global.set ${}", GLOBALS[index as usize]),
+                Load8 => "i32.load8_u".to_string(),
+                Store8 => "i32.store8".to_string(),
+                MemorySize => "memory.size".to_string(),
+                MemoryGrow => "memory.grow".to_string(),
+                Const(value) => format!("i32.const {}", value),
+                Eqz => "i32.eqz".to_string(),
+                Eq => "i32.eq".to_string(),
+                LtS => "i32.lt_s".to_string(),
+                GeU => "i32.ge_u".to_string(),
+                Add => "i32.add".to_string(),
+                Sub => "i32.sub".to_string(),
+                And => "i32.and".to_string(),
+                Or => "i32.or".to_string(),
+                Xor => "i32.xor".to_string(),
+                Shl => "i32.shl".to_string(),
+                ShrS => "i32.shr_s".to_string(),
+                ShrU => "i32.shr_u".to_string()
+            };
+            writeln!(text, "{}{}", "  ".repeat(depth), line).unwrap();
+            if matches!(instr, Block | Loop | If) {
+                depth += 1;
+            }
+        }
+
+        text.push_str("  )\n");
+    }
+
+    text.push_str(")\n");
+    text
+}
+
+fn unsigned(bytes: &mut Vec<u8>, mut value: u32) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes.push(byte);
+            return;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+fn signed(bytes: &mut Vec<u8>, mut value: i32) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if (value == 0 && byte & 0x40 == 0) || (value == -1 && byte & 0x40 != 0) {
+            bytes.push(byte);
+            return;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+fn name(bytes: &mut Vec<u8>, name: &str) {
+    unsigned(bytes, name.len() as u32);
+    bytes.extend_from_slice(name.as_bytes());
+}
+
+/// Appends a section with the given id holding `count` entries.
+fn section(module: &mut Vec<u8>, id: u8, count: usize, entries: Vec<u8>) {
+    let mut contents = vec![];
+    unsigned(&mut contents, count as u32);
+    contents.extend(entries);
+    module.push(id);
+    unsigned(module, contents.len() as u32);
+    module.extend(contents);
+}
+
+fn encode(bytes: &mut Vec<u8>, instr: Instr) {
+    match instr {
+        Unreachable => bytes.push(0x00),
+        Block => bytes.extend([0x02, 0x40]),
+        Loop => bytes.extend([0x03, 0x40]),
+        If => bytes.extend([0x04, 0x40]),
+        End => bytes.push(0x0b),
+        Br(depth) => { bytes.push(0x0c); unsigned(bytes, depth) },
+        BrIf(depth) => { bytes.push(0x0d); unsigned(bytes, depth) },
+        Return => bytes.push(0x0f),
+        Call(index) => { bytes.push(0x10); unsigned(bytes, index) },
+        LocalGet(index) => { bytes.push(0x20); unsigned(bytes, index) },
+        LocalSet(index) => { bytes.push(0x21); unsigned(bytes, index) },
+        LocalTee(index) => { bytes.push(0x22); unsigned(bytes, index) },
+        GlobalGet(index) => { bytes.push(0x23); unsigned(bytes, index) },
+        GlobalSet(index) => { bytes.push(0x24); unsigned(bytes, index) },
+        Load8 => bytes.extend([0x2d, 0, 0]),
+        Store8 => bytes.extend([0x3a, 0, 0]),
+        MemorySize => bytes.extend([0x3f, 0]),
+        MemoryGrow => bytes.extend([0x40, 0]),
+        Const(value) => { bytes.push(0x41); signed(bytes, value) },
+        Eqz => bytes.push(0x45),
+        Eq => bytes.push(0x46),
+        LtS => bytes.push(0x48),
+        GeU => bytes.push(0x4f),
+        Add => bytes.push(0x6a),
+        Sub => bytes.push(0x6b),
+        And => bytes.push(0x71),
+        Or => bytes.push(0x72),
+        Xor => bytes.push(0x73),
+        Shl => bytes.push(0x74),
+        ShrS => bytes.push(0x75),
+        ShrU => bytes.push(0x76)
+    }
+}
+
+/// Translates `program` into a module in the WebAssembly binary format.
+pub fn wasm(program: &Program) -> Vec<u8> {
+    let functions = functions(program);
+    let mut signatures: Vec<Signature> = vec![];
+    let mut type_of = |signature: Signature| {
+        if !signatures.contains(&signature) {
+            signatures.push(signature);
+        }
+        signatures.iter().position(|&s| s == signature).unwrap() as u32
+    };
+    let import_types: Vec<_> = IMPORTS.iter().map(|&(_, signature)| type_of(signature)).collect();
+    let function_types: Vec<_> = functions.iter().map(|function| type_of(function.signature)).collect();
+
+    let mut module = b"\0asm\x01\0\0\0".to_vec();
+
+    let mut types = vec![];
+    for &(params, results) in &signatures {
+        types.push(0x60);
+        unsigned(&mut types, params as u32);
+        types.extend((0 .. params).map(|_| 0x7f));
+        unsigned(&mut types, results as u32);
+        types.extend((0 .. results).map(|_| 0x7f));
+    }
+    section(&mut module, 1, signatures.len(), types);
+
+    let mut imports = vec![];
+    for (&(import, _), &ty) in IMPORTS.iter().zip(&import_types) {
+        name(&mut imports, "env");
+        name(&mut imports, import);
+        imports.push(0x00);
+        unsigned(&mut imports, ty);
+    }
+    section(&mut module, 2, IMPORTS.len(), imports);
+
+    let mut declarations = vec![];
+    for &ty in &function_types {
+        unsigned(&mut declarations, ty);
+    }
+    section(&mut module, 3, functions.len(), declarations);
+
+    section(&mut module, 5, 1, vec![0x00, 1]);
+
+    let mut globals = vec![];
+    for _ in GLOBALS {
+        globals.extend([0x7f, 0x01, 0x41, 0, 0x0b]);
+    }
+    section(&mut module, 6, GLOBALS.len(), globals);
+
+    let mut exports = vec![];
+    name(&mut exports, "memory");
+    exports.extend([0x02, 0]);
+    name(&mut exports, "run");
+    exports.push(0x00);
+    unsigned(&mut exports, (IMPORTS.len() + functions.len() - 1) as u32);
+    section(&mut module, 7, 2, exports);
+
+    let mut code = vec![];
+    for function in &functions {
+        let mut body = vec![];
+        let locals = function.locals.len() - function.signature.0;
+        if locals > 0 {
+            body.push(1);
+            unsigned(&mut body, locals as u32);
+            body.push(0x7f);
+        } else {
+            body.push(0);
+        }
+        for &instr in &function.body {
+            encode(&mut body, instr);
+        }
+        body.push(0x0b);
+
+        unsigned(&mut code, body.len() as u32);
+        code.extend(body);
+    }
+    section(&mut module, 10, functions.len(), code);
+
+    module
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, process};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use testing::*;
+
+    const RUNNER: &str = r#"
+const fs = require("fs");
+const input = fs.readFileSync(0);
+const output = [];
+let at = 0;
+const env = {
+    read_byte: () => at < input.length ? input[at++] : -1,
+    write_byte: byte => output.push(byte)
+};
+WebAssembly.instantiate(fs.readFileSync(process.argv[2]), { env }).then(({ instance }) => {
+    instance.exports.run();
+    process.stdout.write(Buffer.from(output));
+});
+"#;
+
+    /// Runs the binary module for each program on its input with node, or
+    /// returns `None` if there is no node.
+    fn run_wasm(cases: &[(Program, Vec<u8>)]) -> Option<Vec<Vec<u8>>> {
+        // Tests run in parallel, so each call gets its own directory.
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+        let dir = env::temp_dir().join(format!("boolfuck-wasm-{}-{}", process::id(), CALLS.fetch_add(1, Ordering::Relaxed)));
+        fs::create_dir_all(&dir).unwrap();
+        let runner = dir.join("run.js");
+        fs::write(&runner, RUNNER).unwrap();
+
+        let outputs = cases
+            .iter()
+            .enumerate()
+            .map(|(i, (program, input))| {
+                let module = dir.join(format!("program{}.wasm", i));
+                fs::write(&module, wasm(program)).unwrap();
+                run_piped(process::Command::new("node").arg(&runner).arg(&module), input)
+            })
+            .collect();
+        fs::remove_dir_all(&dir).unwrap();
+        outputs
+    }
+
+    #[test]
+    fn test_leb128() {
+        let mut bytes = vec![];
+        unsigned(&mut bytes, 624485);
+        assert_eq!(bytes, [0xe5, 0x8e, 0x26]);
+
+        let mut bytes = vec![];
+        signed(&mut bytes, -123456);
+        assert_eq!(bytes, [0xc0, 0xbb, 0x78]);
+
+        let mut bytes = vec![];
+        signed(&mut bytes, 64);
+        assert_eq!(bytes, [0xc0, 0x00]);
+    }
+
+    #[test]
+    fn test_wat() {
+        let text = wat(&Program::parse("+[>]<[;+]").unwrap());
+        assert!(text.contains("(import \"env\" \"read_byte\" (func $read_byte (result i32)))"));
+        assert!(text.contains("(import \"env\" \"write_byte\" (func $write_byte (param $byte i32)))"));
+        assert!(text.contains("  (func $run (export \"run\")\n    (local $p i32)\n    local.get $p\n    call $flip\n    block\n      loop\n"));
+        assert_eq!(text.matches("block").count(), text.matches("loop").count());
+    }
+
+    #[test]
+    fn test_memory_growth() {
+        // Past the first page of linear memory.
+        let program = Program::parse(&format!("{}+;>+;{}+;+[<]>;", "<".repeat(70), ">".repeat(600_000))).unwrap();
+        if let Some(outputs) = run_wasm(&[(program.clone(), vec![])]) {
+            assert_eq!(outputs[0], interpret(&program, b"", Options::default()).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_matches_interpreter() {
+        assert_generator_matches(run_wasm);
+    }
+}
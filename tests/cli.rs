@@ -0,0 +1,138 @@
+//! Runs the `boolfuck` binary and checks the exit status it promises in
+//! its usage text.
+
+use std::io::Write;
+use std::path::PathBuf;
+use std::io::Read;
+use std::process::{Command, Output, Stdio};
+use std::sync::mpsc;
+use std::time::Duration;
+use std::{env, fs, process, thread};
+
+/// Writes `code` to a file and runs the binary on it with `args` before
+/// the file name and `input` on stdin.
+fn boolfuck(name: &str, code: impl AsRef<[u8]>, args: &[&str], input: &[u8]) -> Output {
+    let path: PathBuf = env::temp_dir().join(format!("boolfuck-cli-{}-{}.bf", process::id(), name));
+    fs::write(&path, code).unwrap();
+    let output = run(args.iter().copied().chain([path.to_str().unwrap()]), input);
+    fs::remove_file(&path).unwrap();
+    output
+}
+
+/// Runs the binary with `args` and `input` on stdin.
+fn run<'a>(args: impl IntoIterator<Item = &'a str>, input: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_boolfuck"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // The program can halt before reading all of its input.
+    let _ = child.stdin.take().unwrap().write_all(input);
+    child.wait_with_output().unwrap()
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn test_halted() {
+    let output = boolfuck("halted", ",;,;,;,;,;,;,;,;", &[], b"k");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, b"k");
+}
+
+#[test]
+fn test_bad_arguments() {
+    let output = boolfuck("arguments", "", &["--eof", "maybe"], b"");
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn test_parse_error() {
+    let output = boolfuck("parse", "+\n]", &[], b"");
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr(&output).contains("line 2, column 1"), "{}", stderr(&output));
+}
+
+#[test]
+fn test_end_of_input() {
+    let output = boolfuck("eof", ",;,;", &["--eof", "error"], b"");
+    assert_eq!(output.status.code(), Some(3));
+}
+
+#[test]
+fn test_steps() {
+    let output = boolfuck("steps", "+[]", &["--steps", "100"], b"");
+    assert_eq!(output.status.code(), Some(4));
+    assert!(stderr(&output).contains("step limit of 100"), "{}", stderr(&output));
+}
+
+#[test]
+fn test_tape_limit() {
+    let output = boolfuck("tape", "+[>+]", &["--tape-limit", "16"], b"");
+    assert_eq!(output.status.code(), Some(4));
+
+    let output = boolfuck("tape-crossing", ">>>", &["--tape-limit", "2"], b"");
+    assert_eq!(output.status.code(), Some(4));
+    assert!(stderr(&output).ends_with("at line 1, column 2\n"), "{}", stderr(&output));
+
+    let output = boolfuck("tape-closures", "+[>+]", &["--tape-limit", "16", "--engine", "closures"], b"");
+    assert_eq!(output.status.code(), Some(4));
+}
+
+#[test]
+fn test_bytecode_has_no_position() {
+    let bytecode = boolfuck("bytecode-emit", ">>>", &["--emit", "bytecode"], b"").stdout;
+    let output = boolfuck("bytecode", bytecode, &["--tape-limit", "2"], b"");
+    assert_eq!(output.status.code(), Some(4));
+    assert_eq!(stderr(&output), "boolfuck: tape grew past 2 cells\n");
+}
+
+#[test]
+fn test_repl_keeps_partial_bytes() {
+    let output = run(["--partial-byte", "pad", "repl"], b"+;\n");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr(&output).contains("--partial-byte cannot be used with repl"), "{}", stderr(&output));
+}
+
+#[test]
+fn test_repl_loop_across_lines() {
+    let output = run(["--tape-limit", "8", "repl"], b"+[>\n+]\n");
+    assert_eq!(output.status.code(), Some(0));
+    assert!(!stderr(&output).contains("unmatched"), "{}", stderr(&output));
+    assert!(stderr(&output).contains("tape grew past 8 cells"), "{}", stderr(&output));
+
+    let output = run(["repl"], b"]\n+[\n");
+    assert!(stderr(&output).contains("unmatched ']' at line 1, column 1"), "{}", stderr(&output));
+    assert!(stderr(&output).contains("unmatched '[' at line 1, column 2"), "{}", stderr(&output));
+}
+
+#[test]
+fn test_output_streams() {
+    // Writes a byte with no newline, then waits on input that never comes
+    // until the byte has been seen.
+    let path: PathBuf = env::temp_dir().join(format!("boolfuck-cli-{}-stream.bf", process::id()));
+    fs::write(&path, boolfuck::brainfuck::translate("+++.,").unwrap()).unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_boolfuck"))
+        .arg(&path)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let mut stdout = child.stdout.take().unwrap();
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut byte = [0];
+        let _ = sender.send(stdout.read_exact(&mut byte).map(|()| byte[0]).ok());
+    });
+    let byte = receiver.recv_timeout(Duration::from_secs(10));
+
+    drop(child.stdin.take());
+    assert!(child.wait().unwrap().success());
+    fs::remove_file(&path).unwrap();
+    assert_eq!(byte, Ok(Some(3)));
+}